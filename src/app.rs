@@ -1,23 +1,99 @@
+use std::time::Instant;
+
 use winit::{
     application::ApplicationHandler,
     event::WindowEvent,
-    event_loop::ActiveEventLoop,
+    event_loop::{ActiveEventLoop, ControlFlow},
     keyboard::{KeyCode, PhysicalKey},
     window::{Window, WindowId},
 };
 
-use crate::State;
+use crate::config::{GpuConfig, Options};
+use crate::frame::{FixedTimestep, FrameErrors, FrameLimiter, FrameOutcome};
+use crate::hot_reload::SceneWatcher;
+use crate::scene::Scene;
+use crate::{RendererError, State};
 
-#[derive(Default)]
 pub struct App {
+    gpu_config: GpuConfig,
+    limiter: FrameLimiter,
+    timestep: FixedTimestep,
+    frame_errors: FrameErrors,
+    // Taken when the first state is created
+    trace_frames: Option<u32>,
+    // Loaded from --scene, taken when the first state is created
+    scene: Option<Scene>,
+    watcher: Option<SceneWatcher>,
     state: Option<State>,
+    // Set when startup fails, run() returns it once the event loop has stopped
+    error: Option<RendererError>,
+}
+impl App {
+    pub fn new(options: Options, scene: Option<Scene>, watcher: Option<SceneWatcher>) -> Self {
+        Self {
+            gpu_config: options.gpu,
+            limiter: FrameLimiter::new(options.target_fps),
+            timestep: FixedTimestep::new(options.tick_rate),
+            frame_errors: FrameErrors::default(),
+            trace_frames: options.trace_frames,
+            scene,
+            watcher,
+            state: None,
+            error: None,
+        }
+    }
+
+    pub fn take_error(&mut self) -> Option<RendererError> {
+        self.error.take()
+    }
+
+    fn create_state(&mut self, event_loop: &ActiveEventLoop) -> Result<State, RendererError> {
+        let window =
+            event_loop.create_window(Window::default_attributes().with_title("Hello WGPU!"))?;
+        State::new(
+            window,
+            self.scene.take(),
+            self.watcher.take(),
+            self.gpu_config.clone(),
+        )
+    }
+
+    // Every resource on a lost device is unusable, so start over from the scene
+    fn recover_lost_device(&mut self, event_loop: &ActiveEventLoop) {
+        if !self.state.as_ref().is_some_and(State::is_device_lost) {
+            return;
+        }
+
+        let Some(state) = self.state.take() else {
+            return;
+        };
+        match state.recover_device() {
+            Ok(state) => self.state = Some(state),
+            Err(error) => {
+                self.error = Some(error);
+                event_loop.exit();
+            }
+        }
+    }
 }
 impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        let window = event_loop
-            .create_window(Window::default_attributes().with_title("Hello WGPU!"))
-            .expect("Failed to create window");
-        self.state = Some(State::new(window));
+        if self.state.is_some() {
+            return;
+        }
+
+        match self.create_state(event_loop) {
+            Ok(mut state) => {
+                if let Some(frames) = self.trace_frames.take() {
+                    state.start_trace(frames);
+                }
+                self.state = Some(state);
+            }
+            Err(error) => {
+                self.error = Some(error);
+                event_loop.exit();
+            }
+        }
     }
 
     fn window_event(
@@ -26,37 +102,52 @@ impl ApplicationHandler for App {
         window_id: WindowId,
         event: WindowEvent,
     ) {
-        let window = self.state.as_ref().unwrap().window();
+        self.recover_lost_device(event_loop);
+
+        let Some(state) = self.state.as_mut() else {
+            return;
+        };
 
-        if window.id() == window_id && !self.state.as_mut().unwrap().input(&event) {
+        if state.window().id() == window_id && !state.input(&event) {
             match event {
                 WindowEvent::Resized(size) => {
-                    println!("Resizing window");
-                    self.state.as_mut().unwrap().resize(size);
+                    log::debug!("Resizing window to {size:?}");
+                    state.resize(size);
+                }
+
+                // Moving to a monitor with another scale changes the physical size. The
+                // window may still report the old one here, winit sends a Resized with the
+                // new size right after
+                WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                    log::info!("Scale factor changed to {scale_factor}");
                 }
 
                 WindowEvent::RedrawRequested => {
-                    self.state.as_mut().unwrap().window().request_redraw();
+                    let now = Instant::now();
+                    self.limiter.frame_started(now);
 
-                    self.state.as_mut().unwrap().update();
+                    let steps = self.timestep.advance(now);
+                    state.update(steps, self.timestep.alpha());
 
-                    match self.state.as_mut().unwrap().render() {
-                        Ok(_) => {}
-                        Err(e) => {
-                            println!("Rendering failed: {:?}", e);
-                        }
+                    if state.is_minimized() {
+                        return;
+                    }
+                    let result = state.render();
+                    if let FrameOutcome::Exit(error) = self.frame_errors.handle(result, state) {
+                        self.error = Some(error.into());
+                        event_loop.exit();
                     }
                 }
 
                 WindowEvent::KeyboardInput { event, .. } => {
                     if let PhysicalKey::Code(KeyCode::Escape) = event.physical_key {
-                        println!("Escape pressed!");
+                        log::info!("Escape pressed, exiting");
                         event_loop.exit();
                     }
                 }
 
                 WindowEvent::CloseRequested => {
-                    println!("Closing window");
+                    log::info!("Closing window");
                     event_loop.exit();
                 }
                 _ => (),
@@ -64,6 +155,27 @@ impl ApplicationHandler for App {
         }
     }
 
+    // Sleeps until the frame limiter allows the next frame instead of spinning
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        let Some(state) = self.state.as_ref() else {
+            return;
+        };
+
+        if state.is_minimized() {
+            // Resizing back wakes the loop up again
+            event_loop.set_control_flow(ControlFlow::Wait);
+            return;
+        }
+
+        match self.limiter.deadline(Instant::now()) {
+            Some(deadline) => event_loop.set_control_flow(ControlFlow::WaitUntil(deadline)),
+            None => {
+                state.window().request_redraw();
+                event_loop.set_control_flow(ControlFlow::Wait);
+            }
+        }
+    }
+
     fn device_event(
         &mut self,
         _event_loop: &ActiveEventLoop,
@@ -72,15 +184,10 @@ impl ApplicationHandler for App {
     ) {
         use winit::event::DeviceEvent;
 
-        match event {
-            DeviceEvent::MouseMotion { delta: (dx, dy) } => {
-                if let Some(state) = self.state.as_mut() {
-                    state
-                        .camera
-                        .process_mouse_motion(dx, dy);
-                }
+        if let DeviceEvent::MouseMotion { delta: (dx, dy) } = event {
+            if let Some(state) = self.state.as_mut() {
+                state.scene.camera.process_mouse_motion(dx, dy);
             }
-            _ => {}
         }
     }
 }
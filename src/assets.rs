@@ -0,0 +1,475 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use crate::loader::{self, Decoded, Loader};
+use crate::material::{Material, MaterialDesc, MaterialTextures, TextureSource};
+use crate::mesh::Mesh;
+use crate::texture::Texture;
+use crate::texture_data::TextureKind;
+use crate::vfs::Vfs;
+use crate::Vertex;
+
+/// Typed reference to an asset in a [`Storage`]. The asset stays loaded as long as a
+/// clone of its handle is alive somewhere.
+pub struct Handle<T> {
+    index: usize,
+    refs: Arc<()>,
+    _asset: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            index: self.index,
+            refs: self.refs.clone(),
+            _asset: PhantomData,
+        }
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        // A slot is only reused once every handle to it is gone
+        self.index == other.index
+    }
+}
+impl<T> Eq for Handle<T> {}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = std::any::type_name::<T>()
+            .rsplit("::")
+            .next()
+            .unwrap_or("?");
+        write!(f, "Handle<{name}>({})", self.index)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LoadState {
+    Loading,
+    Loaded,
+    Failed,
+}
+
+struct Slot<T, K> {
+    key: Option<K>,
+    // The storage holds one reference itself, every handle adds one
+    refs: Arc<()>,
+    state: LoadState,
+    asset: Option<T>,
+}
+
+/// Assets of one type, deduplicated by a key such as their path.
+pub struct Storage<T, K = String> {
+    slots: Vec<Option<Slot<T, K>>>,
+    keys: HashMap<K, usize>,
+}
+
+impl<T, K> Default for Storage<T, K> {
+    fn default() -> Self {
+        Self {
+            slots: Vec::new(),
+            keys: HashMap::new(),
+        }
+    }
+}
+
+impl<T, K: Clone + Eq + Hash> Storage<T, K> {
+    /// Handle to the asset loaded under `key`, if there is one.
+    pub fn find(&self, key: &K) -> Option<Handle<T>> {
+        self.keys.get(key).map(|&index| self.handle(index))
+    }
+
+    /// Adds a slot that is loading until [`Self::finish`] is called for it. Assets without
+    /// a key are never shared.
+    pub fn reserve(&mut self, key: Option<K>) -> Handle<T> {
+        let slot = Slot {
+            key: key.clone(),
+            refs: Arc::new(()),
+            state: LoadState::Loading,
+            asset: None,
+        };
+        let index = match self.slots.iter().position(Option::is_none) {
+            Some(index) => {
+                self.slots[index] = Some(slot);
+                index
+            }
+            None => {
+                self.slots.push(Some(slot));
+                self.slots.len() - 1
+            }
+        };
+        if let Some(key) = key {
+            self.keys.insert(key, index);
+        }
+        self.handle(index)
+    }
+
+    /// Stores the outcome of loading the asset of `handle`.
+    pub fn finish(&mut self, handle: &Handle<T>, result: Option<T>) {
+        let slot = self.slot_mut(handle);
+        slot.state = match result {
+            Some(_) => LoadState::Loaded,
+            None => LoadState::Failed,
+        };
+        slot.asset = result;
+    }
+
+    /// Adds an asset that is already loaded.
+    pub fn insert(&mut self, key: Option<K>, asset: T) -> Handle<T> {
+        let handle = self.reserve(key);
+        self.finish(&handle, Some(asset));
+        handle
+    }
+
+    /// The asset, `None` while it is loading or when it failed to load.
+    pub fn get(&self, handle: &Handle<T>) -> Option<&T> {
+        self.slot(handle).asset.as_ref()
+    }
+
+    pub fn get_mut(&mut self, handle: &Handle<T>) -> Option<&mut T> {
+        self.slot_mut(handle).asset.as_mut()
+    }
+
+    #[cfg(test)]
+    pub fn state(&self, handle: &Handle<T>) -> LoadState {
+        self.slot(handle).state
+    }
+
+    /// Number of assets in the storage, loaded or not.
+    pub fn len(&self) -> usize {
+        self.slots.iter().flatten().count()
+    }
+
+    /// Number of assets that are still loading.
+    pub fn loading(&self) -> usize {
+        self.slots
+            .iter()
+            .flatten()
+            .filter(|slot| slot.state == LoadState::Loading)
+            .count()
+    }
+
+    /// Every loaded asset with a handle to it.
+    pub fn iter(&self) -> impl Iterator<Item = (Handle<T>, &T)> + '_ {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let asset = slot.as_ref()?.asset.as_ref()?;
+            Some((self.handle(index), asset))
+        })
+    }
+
+    /// Drops every asset no handle refers to any more and returns how many there were.
+    pub fn collect_unused(&mut self) -> usize {
+        let mut unused = 0;
+        for entry in &mut self.slots {
+            let Some(slot) = entry else { continue };
+            if Arc::strong_count(&slot.refs) > 1 {
+                continue;
+            }
+            if let Some(key) = &slot.key {
+                self.keys.remove(key);
+            }
+            *entry = None;
+            unused += 1;
+        }
+        unused
+    }
+
+    fn handle(&self, index: usize) -> Handle<T> {
+        let slot = self.slots[index]
+            .as_ref()
+            .expect("handles only point at live slots");
+        Handle {
+            index,
+            refs: slot.refs.clone(),
+            _asset: PhantomData,
+        }
+    }
+
+    fn slot(&self, handle: &Handle<T>) -> &Slot<T, K> {
+        self.slots[handle.index]
+            .as_ref()
+            .expect("a slot is kept while handles to it exist")
+    }
+
+    fn slot_mut(&mut self, handle: &Handle<T>) -> &mut Slot<T, K> {
+        self.slots[handle.index]
+            .as_mut()
+            .expect("a slot is kept while handles to it exist")
+    }
+}
+
+// Bytes of decoded images uploaded per frame, the rest waits for the next frames
+const UPLOAD_BUDGET: usize = 16 << 20;
+
+/// How many textures are still on their way to the GPU.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Progress {
+    pub loading: usize,
+    pub total: usize,
+}
+impl Progress {
+    pub fn is_done(&self) -> bool {
+        self.loading == 0
+    }
+}
+
+/// The same image is uploaded once per kind it is sampled as, which decides its colour
+/// space and compressed format.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureKey {
+    pub path: String,
+    pub kind: TextureKind,
+}
+
+/// Textures, meshes and materials on one device, shared between everything that uses them.
+pub struct Assets {
+    pub textures: Storage<Texture, TextureKey>,
+    pub meshes: Storage<Mesh>,
+    // Not deduplicated, names are only unique within one scene
+    pub materials: Storage<Material>,
+    // Checkerboard shown for colour maps that are loading or failed to load
+    pub placeholder: Texture,
+    // Neutral 1x1 maps for materials without one, data maps also use them as placeholders
+    pub white_srgb: Texture,
+    pub white: Texture,
+    pub flat_normal: Texture,
+    // Where texture paths are read from
+    vfs: Arc<Vfs>,
+    loader: Loader,
+    // Decoded images waiting for their turn to be uploaded
+    decoded: VecDeque<Decoded>,
+}
+
+impl Assets {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, vfs: Arc<Vfs>) -> Self {
+        use wgpu::TextureFormat::{Rgba8Unorm, Rgba8UnormSrgb};
+
+        let checker = image::RgbaImage::from_fn(8, 8, |x, y| {
+            if (x + y) % 2 == 0 {
+                image::Rgba([255, 0, 255, 255])
+            } else {
+                image::Rgba([32, 32, 32, 255])
+            }
+        });
+        let placeholder = Texture::from_image_with_format(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(checker),
+            Some("placeholder"),
+            Rgba8UnormSrgb,
+        );
+
+        Self {
+            textures: Storage::default(),
+            meshes: Storage::default(),
+            materials: Storage::default(),
+            placeholder,
+            white_srgb: Texture::from_color(device, queue, [255; 4], Rgba8UnormSrgb, "white_srgb"),
+            white: Texture::from_color(device, queue, [255; 4], Rgba8Unorm, "white"),
+            // Flat tangent space normal
+            flat_normal: Texture::from_color(
+                device,
+                queue,
+                [128, 128, 255, 255],
+                Rgba8Unorm,
+                "flat_normal",
+            ),
+            vfs,
+            // Decoded images are compressed on the workers where the GPU can sample BC
+            loader: Loader::new(
+                device
+                    .features()
+                    .contains(wgpu::Features::TEXTURE_COMPRESSION_BC),
+            ),
+            decoded: VecDeque::new(),
+        }
+    }
+
+    /// Starts reading and decoding the image of `source` in the background, or returns the texture
+    /// already loaded from its path. Until it is uploaded by [`Self::update`], materials
+    /// show the placeholder in its place.
+    pub fn load_texture(&mut self, source: &TextureSource, kind: TextureKind) -> Handle<Texture> {
+        let key = TextureKey {
+            path: source.path.clone(),
+            kind,
+        };
+        if let Some(handle) = self.textures.find(&key) {
+            return handle;
+        }
+
+        let handle = self.textures.reserve(Some(key));
+        self.loader
+            .load(handle.clone(), &source.path, kind, &self.vfs);
+        handle
+    }
+
+    /// Uploads the images decoded since the last call, as many as fit into the frame's
+    /// budget, and points the materials using them at the new textures. Returns how many
+    /// textures finished loading, including the ones that failed.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+    ) -> usize {
+        self.decoded.extend(self.loader.finished());
+        let count = loader::uploads_within(self.decoded.iter().map(Decoded::size), UPLOAD_BUDGET);
+        if count == 0 {
+            return 0;
+        }
+
+        let mut uploaded = Vec::new();
+        for decoded in self.decoded.drain(..count) {
+            let data = decoded
+                .data
+                .and_then(|data| data.supported(device.features()));
+            let texture = match data {
+                Ok(data) => Texture::from_data(device, queue, &data, Some(&decoded.path)),
+                Err(error) => {
+                    log::warn!("Failed to load texture {}: {error:#}", decoded.path);
+                    self.textures.finish(&decoded.handle, None);
+                    continue;
+                }
+            };
+            self.textures.finish(&decoded.handle, Some(texture));
+            uploaded.push(decoded.handle);
+        }
+
+        let rebound = self
+            .materials
+            .iter()
+            .filter(|(_, material)| uploaded.iter().any(|texture| material.uses(texture)))
+            .map(|(handle, material)| (handle, material.create_bind_group(device, layout, self)))
+            .collect::<Vec<_>>();
+        for (handle, bind_group) in rebound {
+            if let Some(material) = self.materials.get_mut(&handle) {
+                material.bind_group = bind_group;
+            }
+        }
+        count
+    }
+
+    pub fn progress(&self) -> Progress {
+        Progress {
+            loading: self.textures.loading(),
+            total: self.textures.len(),
+        }
+    }
+
+    /// Uploads a mesh, or returns the one already uploaded under `name`.
+    pub fn load_mesh(
+        &mut self,
+        device: &wgpu::Device,
+        name: &str,
+        vertices: &[Vertex],
+        indices: &[u16],
+    ) -> Handle<Mesh> {
+        let key = name.to_string();
+        if let Some(handle) = self.meshes.find(&key) {
+            return handle;
+        }
+        let mesh = Mesh::new(device, name, vertices, indices);
+        self.meshes.insert(Some(key), mesh)
+    }
+
+    /// Creates a material, sharing the textures that are already loaded.
+    pub fn load_material(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        desc: &MaterialDesc,
+    ) -> Handle<Material> {
+        let mut load = |source: &Option<TextureSource>, kind| {
+            source
+                .as_ref()
+                .map(|source| self.load_texture(source, kind))
+        };
+        let textures = MaterialTextures {
+            base_color: load(&desc.base_color, TextureKind::Color),
+            metallic_roughness: load(&desc.metallic_roughness, TextureKind::Data),
+            normal: load(&desc.normal, TextureKind::Normal),
+            occlusion: load(&desc.occlusion, TextureKind::Mask),
+            emissive: load(&desc.emissive, TextureKind::Color),
+        };
+        let material = Material::new(
+            device,
+            queue,
+            layout,
+            &desc.name,
+            textures,
+            desc.params,
+            self,
+        );
+        self.materials.insert(None, material)
+    }
+
+    /// Unloads every asset nothing refers to any more and returns how many there were.
+    pub fn collect_unused(&mut self) -> usize {
+        // Materials first, dropping them releases their textures
+        self.materials.collect_unused()
+            + self.textures.collect_unused()
+            + self.meshes.collect_unused()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assets_are_deduplicated_by_key() {
+        let mut storage = Storage::<u32>::default();
+        let a = storage.insert(Some("a.png".to_string()), 1);
+        let b = storage.insert(Some("b.png".to_string()), 2);
+        let unnamed = storage.insert(None, 3);
+
+        assert_eq!(storage.find(&"a.png".to_string()), Some(a.clone()));
+        assert_ne!(a, b);
+        assert_eq!(storage.get(&b), Some(&2));
+        assert_eq!(storage.get(&unnamed), Some(&3));
+        assert_eq!(storage.len(), 3);
+    }
+
+    #[test]
+    fn assets_without_handles_are_unloaded() {
+        let mut storage = Storage::<u32>::default();
+        let kept = storage.insert(Some("kept".to_string()), 1);
+        let shared = storage.insert(Some("shared".to_string()), 2);
+        let copy = shared.clone();
+        drop(storage.insert(Some("dropped".to_string()), 3));
+
+        assert_eq!(storage.collect_unused(), 1);
+        assert_eq!(storage.find(&"dropped".to_string()), None);
+
+        drop(shared);
+        assert_eq!(storage.collect_unused(), 0);
+        drop(copy);
+        assert_eq!(storage.collect_unused(), 1);
+        assert_eq!(storage.len(), 1);
+        assert_eq!(storage.get(&kept), Some(&1));
+
+        // The freed slots are reused
+        let reused = storage.insert(None, 4);
+        assert_eq!(storage.get(&reused), Some(&4));
+        assert_eq!(storage.len(), 2);
+    }
+
+    #[test]
+    fn failed_loads_have_no_asset() {
+        let mut storage = Storage::<u32>::default();
+        let handle = storage.reserve(Some("broken.png".to_string()));
+        assert_eq!(storage.state(&handle), LoadState::Loading);
+
+        storage.finish(&handle, None);
+        assert_eq!(storage.state(&handle), LoadState::Failed);
+        assert_eq!(storage.get(&handle), None);
+        // Still deduplicated, so a broken file is not decoded again by every user
+        assert_eq!(storage.find(&"broken.png".to_string()), Some(handle));
+    }
+}
@@ -0,0 +1,280 @@
+// Block compression encoders for the texture import. Every 4x4 block of texels becomes one
+// BC7 block of 16 bytes, one BC5 block of 16 or one BC4 block of 8. They favour speed over
+// quality: BC7 only uses mode 6, one pair of RGBA endpoints with 16 steps between them.
+
+type Block = [[u8; 4]; 16];
+
+// Steps of the 4 bit BC7 indices, out of 64
+const BC7_WEIGHTS: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// BC7 blocks of an RGBA8 image whose sides are multiples of 4.
+pub fn encode_bc7(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    blocks(rgba, width, height)
+        .flat_map(|block| bc7_block(&block))
+        .collect()
+}
+
+/// BC5 blocks of the red and green channels, as used by normal maps.
+pub fn encode_bc5(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    blocks(rgba, width, height)
+        .flat_map(|block| {
+            let mut bytes = [0; 16];
+            bytes[..8].copy_from_slice(&bc4_block(block.map(|texel| texel[0])));
+            bytes[8..].copy_from_slice(&bc4_block(block.map(|texel| texel[1])));
+            bytes
+        })
+        .collect()
+}
+
+/// BC4 blocks of the red channel.
+pub fn encode_bc4(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    blocks(rgba, width, height)
+        .flat_map(|block| bc4_block(block.map(|texel| texel[0])))
+        .collect()
+}
+
+// Blocks in rows from the top left, texels in rows within each block
+fn blocks(rgba: &[u8], width: u32, height: u32) -> impl Iterator<Item = Block> + '_ {
+    let (width, height) = (width as usize, height as usize);
+    (0..height / 4).flat_map(move |by| {
+        (0..width / 4).map(move |bx| {
+            std::array::from_fn(|i| {
+                let (x, y) = (bx * 4 + i % 4, by * 4 + i / 4);
+                let start = (y * width + x) * 4;
+                rgba[start..start + 4].try_into().unwrap()
+            })
+        })
+    })
+}
+
+fn bc7_block(block: &Block) -> [u8; 16] {
+    let texels = block.map(|texel| texel.map(f32::from));
+    let (low, high) = principal_extent(&texels);
+    let mut endpoints = [quantize_endpoint(low), quantize_endpoint(high)];
+    let [e0, e1] = endpoints.map(|(color, p)| color.map(|c| c << 1 | p));
+    let colors: [[u32; 4]; 16] =
+        BC7_WEIGHTS.map(|w| std::array::from_fn(|c| ((64 - w) * e0[c] + w * e1[c] + 32) >> 6));
+    let mut indices = block.map(|texel| nearest(&colors, texel.map(u32::from)));
+
+    // The first index is stored without its top bit, so it has to be below 8
+    if indices[0] >= 8 {
+        endpoints.swap(0, 1);
+        indices = indices.map(|index| 15 - index);
+    }
+
+    let mut bits = Bits::default();
+    bits.push(1 << 6, 7);
+    for channel in 0..4 {
+        bits.push(endpoints[0].0[channel], 7);
+        bits.push(endpoints[1].0[channel], 7);
+    }
+    bits.push(endpoints[0].1, 1);
+    bits.push(endpoints[1].1, 1);
+    bits.push(indices[0], 3);
+    for &index in &indices[1..] {
+        bits.push(index, 4);
+    }
+    bits.0.to_le_bytes()
+}
+
+// Ends of the line through the texels along the direction they vary most in
+fn principal_extent(texels: &[[f32; 4]; 16]) -> ([f32; 4], [f32; 4]) {
+    let mean: [f32; 4] =
+        std::array::from_fn(|c| texels.iter().map(|texel| texel[c]).sum::<f32>() / 16.0);
+    let mut covariance = [[0.0f32; 4]; 4];
+    for texel in texels {
+        let d: [f32; 4] = std::array::from_fn(|c| texel[c] - mean[c]);
+        for (i, row) in covariance.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value += d[i] * d[j];
+            }
+        }
+    }
+    // Power iteration, starting from the channel that varies most so the start is never
+    // at right angles to the answer. A flat block keeps a zero axis and ends at its mean.
+    let widest = (0..4)
+        .max_by(|&a, &b| covariance[a][a].total_cmp(&covariance[b][b]))
+        .unwrap();
+    let mut axis = covariance[widest];
+    for _ in 0..8 {
+        let next: [f32; 4] =
+            std::array::from_fn(|i| (0..4).map(|j| covariance[i][j] * axis[j]).sum());
+        let length = next.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if length < 1e-6 {
+            break;
+        }
+        axis = next.map(|v| v / length);
+    }
+    let project = |texel: &[f32; 4]| (0..4).map(|c| (texel[c] - mean[c]) * axis[c]).sum::<f32>();
+    let (min, max) = texels
+        .iter()
+        .map(project)
+        .fold((0.0f32, 0.0f32), |(min, max), t| (min.min(t), max.max(t)));
+    let point = |t: f32| std::array::from_fn(|c| (mean[c] + axis[c] * t).clamp(0.0, 255.0));
+    (point(min), point(max))
+}
+
+// Seven bits per channel plus a low bit shared by all four, whichever of the two is closer
+fn quantize_endpoint(color: [f32; 4]) -> ([u32; 4], u32) {
+    (0..2)
+        .map(|p| {
+            let quantized = color.map(|c| ((c - p as f32) / 2.0).round().clamp(0.0, 127.0) as u32);
+            let error: f32 = (0..4)
+                .map(|c| (color[c] - (quantized[c] << 1 | p) as f32).powi(2))
+                .sum();
+            (quantized, p, error)
+        })
+        .min_by(|a, b| a.2.total_cmp(&b.2))
+        .map(|(quantized, p, _)| (quantized, p))
+        .unwrap()
+}
+
+fn nearest<const N: usize>(colors: &[[u32; N]], texel: [u32; N]) -> u32 {
+    let error = |color: &[u32; N]| {
+        (0..N)
+            .map(|c| color[c].abs_diff(texel[c]).pow(2))
+            .sum::<u32>()
+    };
+    (0..colors.len())
+        .min_by_key(|&i| error(&colors[i]))
+        .unwrap() as u32
+}
+
+fn bc4_block(values: [u8; 16]) -> [u8; 8] {
+    let (min, max) = (*values.iter().min().unwrap(), *values.iter().max().unwrap());
+    let mut bytes = [max, min, 0, 0, 0, 0, 0, 0];
+    if min == max {
+        return bytes;
+    }
+    // With the first end above the second, six steps lie between them
+    let (high, low) = (u32::from(max), u32::from(min));
+    let palette: [[u32; 1]; 8] = std::array::from_fn(|i| match i {
+        0 => [high],
+        1 => [low],
+        i => [((8 - i as u32) * high + (i as u32 - 1) * low) / 7],
+    });
+    let mut indices = 0u64;
+    for (i, &value) in values.iter().enumerate() {
+        indices |= u64::from(nearest(&palette, [u32::from(value)])) << (3 * i);
+    }
+    bytes[2..].copy_from_slice(&indices.to_le_bytes()[..6]);
+    bytes
+}
+
+// Bits written from the lowest up, as the block formats lay them out
+#[derive(Default)]
+struct Bits(u128, u32);
+
+impl Bits {
+    fn push(&mut self, value: u32, count: u32) {
+        self.0 |= u128::from(value & ((1 << count) - 1)) << self.1;
+        self.1 += count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bits(block: &[u8], start: u32, count: u32) -> u32 {
+        let mut padded = [0; 16];
+        padded[..block.len()].copy_from_slice(block);
+        ((u128::from_le_bytes(padded) >> start) & ((1 << count) - 1)) as u32
+    }
+
+    // Mode 6 only, the one the encoder writes
+    fn decode_bc7(block: &[u8]) -> [[u8; 4]; 16] {
+        assert_eq!(bits(block, 0, 7), 1 << 6);
+        let p = [bits(block, 63, 1), bits(block, 64, 1)];
+        let endpoint = |e: usize| -> [u32; 4] {
+            std::array::from_fn(|c| bits(block, 7 + 14 * c as u32 + 7 * e as u32, 7) << 1 | p[e])
+        };
+        let (e0, e1) = (endpoint(0), endpoint(1));
+        std::array::from_fn(|i| {
+            let index = if i == 0 {
+                bits(block, 65, 3)
+            } else {
+                bits(block, 68 + 4 * (i as u32 - 1), 4)
+            };
+            let w = BC7_WEIGHTS[index as usize];
+            std::array::from_fn(|c| (((64 - w) * e0[c] + w * e1[c] + 32) >> 6) as u8)
+        })
+    }
+
+    fn decode_bc4(block: &[u8]) -> [u8; 16] {
+        let (r0, r1) = (u32::from(block[0]), u32::from(block[1]));
+        std::array::from_fn(|i| {
+            let index = bits(block, 16 + 3 * i as u32, 3);
+            let value = match index {
+                0 => r0,
+                1 => r1,
+                i if r0 > r1 => ((8 - i) * r0 + (i - 1) * r1) / 7,
+                6 => 0,
+                7 => 255,
+                i => ((6 - i) * r0 + (i - 1) * r1) / 5,
+            };
+            value as u8
+        })
+    }
+
+    // Changes along x only, so every block lies on one line as mode 6 needs
+    fn gradient(width: u32, height: u32) -> Vec<u8> {
+        (0..width * height)
+            .flat_map(|i| {
+                let x = i % width;
+                [
+                    (x * 30) as u8,
+                    (x * 20 + 10) as u8,
+                    200 - (x * 20) as u8,
+                    255,
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bc7_blocks_decode_close_to_the_source() {
+        let rgba = gradient(8, 4);
+        let encoded = encode_bc7(&rgba, 8, 4);
+        assert_eq!(encoded.len(), 2 * 16);
+        for (b, block) in blocks(&rgba, 8, 4).enumerate() {
+            let decoded = decode_bc7(&encoded[b * 16..(b + 1) * 16]);
+            for (texel, expected) in decoded.iter().zip(&block) {
+                for c in 0..4 {
+                    assert!(
+                        texel[c].abs_diff(expected[c]) <= 12,
+                        "{texel:?} {expected:?}"
+                    );
+                }
+            }
+        }
+
+        let flat = [[90, 20, 240, 128]; 16].concat();
+        let decoded = decode_bc7(&encode_bc7(&flat, 4, 4));
+        assert!(decoded.iter().all(|texel| texel
+            .iter()
+            .zip([90, 20, 240, 128])
+            .all(|(&a, b)| a.abs_diff(b) <= 1)));
+    }
+
+    #[test]
+    fn bc4_and_bc5_keep_their_channels() {
+        let rgba = gradient(4, 4);
+        let bc4 = encode_bc4(&rgba, 4, 4);
+        assert_eq!(bc4.len(), 8);
+        let red = decode_bc4(&bc4);
+        for (i, value) in red.iter().enumerate() {
+            assert!(value.abs_diff(rgba[i * 4]) <= 8);
+        }
+
+        let bc5 = encode_bc5(&rgba, 4, 4);
+        assert_eq!(bc5.len(), 16);
+        assert_eq!(&bc5[..8], &bc4[..]);
+        let green = decode_bc4(&bc5[8..]);
+        for (i, value) in green.iter().enumerate() {
+            assert!(value.abs_diff(rgba[i * 4 + 1]) <= 12);
+        }
+
+        assert_eq!(decode_bc4(&encode_bc4(&[7; 64], 4, 4)), [7; 16]);
+    }
+}
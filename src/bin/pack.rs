@@ -0,0 +1,93 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use rust_wgpu::pack;
+
+const USAGE: &str = "\
+Usage: pack <directory> <output> [options]
+
+Packs every file under the directory into one asset pack, which rust_wgpu reads with --pack.
+
+Options:
+  --store              Keep every file as it is instead of compressing the ones that shrink
+  -h, --help           Show this help
+";
+
+fn main() {
+    if let Err(error) = run() {
+        eprintln!("Error: {error:#}");
+        std::process::exit(1);
+    }
+}
+
+fn run() -> anyhow::Result<()> {
+    let mut paths = Vec::new();
+    let mut compress = true;
+    for argument in std::env::args().skip(1) {
+        match argument.as_str() {
+            "--store" => compress = false,
+            "-h" | "--help" => {
+                print!("{USAGE}");
+                return Ok(());
+            }
+            option if option.starts_with('-') => bail!("unknown option {option}, see --help"),
+            _ => paths.push(PathBuf::from(argument)),
+        }
+    }
+    let [directory, output] = paths.as_slice() else {
+        bail!("expected a directory and an output file, see --help");
+    };
+    if !directory.is_dir() {
+        bail!("{} is not a directory", directory.display());
+    }
+
+    let mut files = Vec::new();
+    collect(directory, directory, &mut files)?;
+    // Skip the pack itself when it is written into the directory it packs
+    let output_path = std::path::absolute(output)?;
+    files.retain(|(path, _)| std::path::absolute(path).ok().as_ref() != Some(&output_path));
+    files.sort();
+
+    let mut contents = Vec::with_capacity(files.len());
+    let mut size = 0;
+    for (path, name) in files {
+        let bytes =
+            std::fs::read(&path).with_context(|| format!("could not read {}", path.display()))?;
+        size += bytes.len();
+        contents.push((name, bytes));
+    }
+    let count = contents.len();
+
+    let file =
+        File::create(output).with_context(|| format!("could not create {}", output.display()))?;
+    let packed = pack::write(BufWriter::new(file), contents, compress)?
+        .into_inner()
+        .map_err(|error| error.into_error())?;
+    let packed_size = packed.metadata()?.len();
+    println!(
+        "Packed {count} files, {size} bytes into {packed_size} in {}",
+        output.display()
+    );
+    Ok(())
+}
+
+// Every file under `dir`, with its path relative to `root` in the form the vfs looks it up by
+fn collect(root: &Path, dir: &Path, files: &mut Vec<(PathBuf, String)>) -> anyhow::Result<()> {
+    let entries =
+        std::fs::read_dir(dir).with_context(|| format!("could not read {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect(root, &path, files)?;
+        } else {
+            let name = path
+                .strip_prefix(root)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            files.push((path, name));
+        }
+    }
+    Ok(())
+}
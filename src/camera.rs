@@ -2,11 +2,36 @@ use cgmath::SquareMatrix;
 use winit::event::{ElementState, KeyEvent, WindowEvent};
 use winit::keyboard::{KeyCode, PhysicalKey};
 
-use crate::State;
+// cgmath builds projections for OpenGL's -1..1 depth range, wgpu expects 0..1
+#[rustfmt::skip]
+pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+// Data uploaded to the camera uniform buffer
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CameraUniform {
+    view_proj: [[f32; 4]; 4],
+    // Inverse of the view projection without translation, turns clip positions into sky directions
+    inv_sky_view_proj: [[f32; 4]; 4],
+    view_position: [f32; 4],
+}
+
 pub struct Camera {
     pos: cgmath::Point3<f32>,
+    // Position before the last fixed update
+    previous_pos: cgmath::Point3<f32>,
+    // Position blended between the two that the view is rendered from
+    eye: cgmath::Point3<f32>,
     // target: cgmath::Point3<f32>,
     up: cgmath::Vector3<f32>,
+    // World matrix of the node the camera is attached to, the position and look
+    // direction are relative to it
+    parent: cgmath::Matrix4<f32>,
     yaw: f32,
     pitch: f32,
     aspect: f32,
@@ -14,7 +39,7 @@ pub struct Camera {
     znear: f32,
     zfar: f32,
 
-    view_proj: [[f32; 4]; 4],
+    uniform: CameraUniform,
 
     speed: f32,
     sensitivity: f32,
@@ -29,16 +54,24 @@ impl Camera {
     pub fn default(aspect: f32) -> Self {
         Self {
             pos: (-5.0, 0.0, 0.0).into(),
+            previous_pos: (-5.0, 0.0, 0.0).into(),
+            eye: (-5.0, 0.0, 0.0).into(),
             pitch: 0.0,
             yaw: 0.0,
             up: cgmath::Vector3::unit_y(), // Set the UP direction
+            parent: cgmath::Matrix4::identity(),
             aspect,
             _fovy: 90.0,
             znear: 0.1,
             zfar: 100.0,
 
-            view_proj: cgmath::Matrix4::identity().into(),
+            uniform: CameraUniform {
+                view_proj: cgmath::Matrix4::identity().into(),
+                inv_sky_view_proj: cgmath::Matrix4::identity().into(),
+                view_position: [0.0; 4],
+            },
 
+            // Distance moved per fixed update
             speed: 0.1,
             sensitivity: 0.005,
             is_backward_pressed: false,
@@ -50,8 +83,8 @@ impl Camera {
         }
     }
 
-    fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
-        use cgmath::{InnerSpace, Matrix4, Rad, Vector3};
+    fn build_view_and_projection(&self) -> (cgmath::Matrix4<f32>, cgmath::Matrix4<f32>) {
+        use cgmath::{InnerSpace, Matrix4, Rad, Transform, Vector3};
 
         let (yaw, pitch) = (Rad(self.yaw), Rad(self.pitch));
 
@@ -63,8 +96,10 @@ impl Camera {
         )
         .normalize();
 
-        let target = self.pos + direction;
-        let view = Matrix4::look_at_rh(self.pos, target, self.up);
+        let eye = self.world_eye();
+        let direction = self.parent.transform_vector(direction).normalize();
+        let up = self.parent.transform_vector(self.up).normalize();
+        let view = Matrix4::look_to_rh(eye, direction, up);
 
         let proj = cgmath::perspective(
             Rad(std::f32::consts::FRAC_PI_4),
@@ -73,7 +108,7 @@ impl Camera {
             self.zfar,
         );
 
-        return proj * view;
+        (view, OPENGL_TO_WGPU_MATRIX * proj)
     }
 
     pub fn process_events(&mut self, event: &WindowEvent) -> bool {
@@ -122,6 +157,8 @@ impl Camera {
     pub fn update_camera(&mut self) {
         use cgmath::{InnerSpace, Rad, Vector3, Zero};
 
+        self.previous_pos = self.pos;
+
         let yaw = Rad(self.yaw);
         let pitch = Rad(self.pitch);
 
@@ -159,9 +196,22 @@ impl Camera {
         if movement.magnitude2() > 0.0 {
             self.pos += movement.normalize() * self.speed;
         }
+    }
 
+    /// Places the eye `alpha` of the way from the previous position to the current one,
+    /// relative to `parent`. Mouse look is applied directly, so it stays responsive at any
+    /// tick rate.
+    pub fn interpolate(&mut self, alpha: f32, parent: cgmath::Matrix4<f32>) {
+        self.eye = self.previous_pos + (self.pos - self.previous_pos) * alpha;
+        self.parent = parent;
         self.update_view_proj();
     }
+
+    fn world_eye(&self) -> cgmath::Point3<f32> {
+        use cgmath::Transform;
+
+        self.parent.transform_point(self.eye)
+    }
     pub fn process_mouse_motion(&mut self, dx: f64, dy: f64) {
         self.yaw += dx as f32 * self.sensitivity;
         self.pitch -= dy as f32 * self.sensitivity;
@@ -172,11 +222,36 @@ impl Camera {
         self.pitch = self.pitch.clamp(MIN_PITCH, MAX_PITCH);
     }
     fn update_view_proj(&mut self) {
-        self.view_proj = self.build_view_projection_matrix().into();
+        let (view, proj) = self.build_view_and_projection();
+        self.uniform.view_proj = (proj * view).into();
+
+        let mut sky_view = view;
+        sky_view.w = cgmath::Vector4::unit_w();
+        self.uniform.inv_sky_view_proj = (proj * sky_view)
+            .invert()
+            .unwrap_or(cgmath::Matrix4::identity())
+            .into();
+
+        self.uniform.view_position = self.world_eye().to_homogeneous().into();
+    }
+
+    /// Position relative to the node the camera is attached to, and the look angles in radians.
+    pub fn pose(&self) -> (cgmath::Point3<f32>, f32, f32) {
+        (self.pos, self.yaw, self.pitch)
+    }
+
+    /// Moves the camera without interpolating from where it was.
+    pub fn set_pose(&mut self, position: cgmath::Point3<f32>, yaw: f32, pitch: f32) {
+        self.pos = position;
+        self.previous_pos = position;
+        self.eye = position;
+        self.yaw = yaw;
+        self.pitch = pitch;
+        self.update_view_proj();
     }
 
-    pub fn view_proj(&self) -> [[f32; 4]; 4] {
-        self.view_proj
+    pub fn uniform(&self) -> CameraUniform {
+        self.uniform
     }
 
     pub fn update_aspect(&mut self, aspect: f32) {
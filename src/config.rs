@@ -0,0 +1,334 @@
+use std::path::PathBuf;
+
+use crate::RendererError;
+
+pub const USAGE: &str = "\
+Usage: rust_wgpu [options]
+
+Options:
+  --backend <list>     Comma separated backends to try: vulkan, metal, dx12, gl, primary or all
+  --power <pref>       Adapter power preference: low, high or none
+  --fallback           Use a software adapter such as llvmpipe or WARP
+  --feature <name>     Enable a wgpu feature if the adapter supports it, some are on by default
+  --present-mode <m>   fifo, fifo-relaxed, mailbox, immediate, auto-vsync or auto-no-vsync
+  --fps <n>            Limit rendering to n frames per second
+  --tick-rate <hz>     Simulation updates per second, 60 by default
+  --trace <frames>     Write the first n frames to trace.json, F5 traces later frames
+  --scene <path>       Show the scene in a .ron or .json file instead of the demo, reloaded when it changes
+  --assets <dir>       Read assets from this directory before the built-in ones, assets by default
+  --pack <file>        Read assets from a pack made with the pack tool, assets.pak by default, repeatable
+  --cache <dir>        Keep block compressed textures here between runs, in the temp directory by default
+  --list-adapters      Print the available adapters and exit
+  --help               Print this message and exit";
+
+/// How the adapter is picked and what is asked of the device.
+#[derive(Debug, Clone)]
+pub struct GpuConfig {
+    pub backends: wgpu::Backends,
+    pub power_preference: wgpu::PowerPreference,
+    pub force_fallback_adapter: bool,
+    // Requested only when the adapter has them, the renderer works without
+    pub optional_features: wgpu::Features,
+    // Falls back to the closest supported mode, see gpu::choose_present_mode
+    pub present_mode: wgpu::PresentMode,
+}
+impl Default for GpuConfig {
+    fn default() -> Self {
+        Self {
+            backends: wgpu::Backends::PRIMARY,
+            power_preference: wgpu::PowerPreference::default(),
+            force_fallback_adapter: false,
+            // Times the render passes on the GPU, and lets textures stay block compressed
+            // or in full floats instead of being refused or converted to half floats
+            optional_features: wgpu::Features::TIMESTAMP_QUERY
+                | wgpu::Features::TEXTURE_COMPRESSION_BC
+                | wgpu::Features::FLOAT32_FILTERABLE
+                | wgpu::Features::TEXTURE_BINDING_ARRAY
+                | wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING,
+            present_mode: wgpu::PresentMode::AutoVsync,
+        }
+    }
+}
+
+/// Command line options.
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub gpu: GpuConfig,
+    pub target_fps: Option<f64>,
+    pub tick_rate: f64,
+    // Frames to trace from startup
+    pub trace_frames: Option<u32>,
+    // Shown instead of the demo scene
+    pub scene: Option<PathBuf>,
+    // Loose asset directory, vfs::DEFAULT_ROOT when it exists if not given
+    pub assets: Option<PathBuf>,
+    // Searched after the loose assets, in order, vfs::DEFAULT_PACK when none are given
+    pub packs: Vec<PathBuf>,
+    pub cache: Option<PathBuf>,
+    pub list_adapters: bool,
+    pub help: bool,
+}
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            gpu: GpuConfig::default(),
+            target_fps: None,
+            tick_rate: 60.0,
+            trace_frames: None,
+            scene: None,
+            assets: None,
+            packs: Vec::new(),
+            cache: None,
+            list_adapters: false,
+            help: false,
+        }
+    }
+}
+
+impl Options {
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, RendererError> {
+        let mut options = Self::default();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| RendererError::Arguments(format!("{name} needs a value")))
+            };
+
+            match arg.as_str() {
+                "--backend" => options.gpu.backends = parse_backends(&value("--backend")?)?,
+                "--power" => options.gpu.power_preference = parse_power(&value("--power")?)?,
+                "--fallback" => options.gpu.force_fallback_adapter = true,
+                "--feature" => {
+                    options.gpu.optional_features |= parse_feature(&value("--feature")?)?
+                }
+                "--present-mode" => {
+                    options.gpu.present_mode = parse_present_mode(&value("--present-mode")?)?
+                }
+                "--fps" => options.target_fps = Some(parse_rate("--fps", &value("--fps")?)?),
+                "--tick-rate" => {
+                    options.tick_rate = parse_rate("--tick-rate", &value("--tick-rate")?)?
+                }
+                "--trace" => {
+                    options.trace_frames = Some(parse_count("--trace", &value("--trace")?)?)
+                }
+                "--scene" => options.scene = Some(value("--scene")?.into()),
+                "--assets" => options.assets = Some(value("--assets")?.into()),
+                "--pack" => options.packs.push(value("--pack")?.into()),
+                "--cache" => options.cache = Some(value("--cache")?.into()),
+                "--list-adapters" => options.list_adapters = true,
+                "--help" | "-h" => options.help = true,
+                _ => return Err(RendererError::Arguments(format!("unknown option {arg}"))),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn parse_backends(list: &str) -> Result<wgpu::Backends, RendererError> {
+    let backends = match list.to_lowercase().as_str() {
+        "primary" => wgpu::Backends::PRIMARY,
+        "all" => wgpu::Backends::all(),
+        list => wgpu::Backends::from_comma_list(list),
+    };
+    if backends.is_empty() {
+        return Err(RendererError::Arguments(format!(
+            "no known backend in {list}"
+        )));
+    }
+    Ok(backends)
+}
+
+fn parse_power(name: &str) -> Result<wgpu::PowerPreference, RendererError> {
+    match name.to_lowercase().as_str() {
+        "low" => Ok(wgpu::PowerPreference::LowPower),
+        "high" => Ok(wgpu::PowerPreference::HighPerformance),
+        "none" => Ok(wgpu::PowerPreference::None),
+        _ => Err(RendererError::Arguments(format!(
+            "unknown power preference {name}, expected low, high or none"
+        ))),
+    }
+}
+
+fn parse_feature(name: &str) -> Result<wgpu::Features, RendererError> {
+    wgpu::Features::from_name(&name.to_uppercase())
+        .ok_or_else(|| RendererError::Arguments(format!("unknown feature {name}")))
+}
+
+fn parse_present_mode(name: &str) -> Result<wgpu::PresentMode, RendererError> {
+    match name.to_lowercase().as_str() {
+        "fifo" => Ok(wgpu::PresentMode::Fifo),
+        "fifo-relaxed" => Ok(wgpu::PresentMode::FifoRelaxed),
+        "mailbox" => Ok(wgpu::PresentMode::Mailbox),
+        "immediate" => Ok(wgpu::PresentMode::Immediate),
+        "auto-vsync" => Ok(wgpu::PresentMode::AutoVsync),
+        "auto-no-vsync" => Ok(wgpu::PresentMode::AutoNoVsync),
+        _ => Err(RendererError::Arguments(format!(
+            "unknown present mode {name}"
+        ))),
+    }
+}
+
+fn parse_rate(name: &str, value: &str) -> Result<f64, RendererError> {
+    match value.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        _ => Err(RendererError::Arguments(format!(
+            "{name} needs a positive number, got {value}"
+        ))),
+    }
+}
+
+fn parse_count(name: &str, value: &str) -> Result<u32, RendererError> {
+    match value.parse::<u32>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(RendererError::Arguments(format!(
+            "{name} needs a whole number above zero, got {value}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, RendererError> {
+        Options::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    // The message of an argument error, panics on success or any other error
+    fn error(args: &[&str]) -> String {
+        match parse(args) {
+            Err(RendererError::Arguments(message)) => message,
+            other => panic!("expected an argument error for {args:?}, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn no_arguments_give_the_defaults() {
+        let options = parse(&[]).unwrap();
+        assert_eq!(options.gpu.backends, wgpu::Backends::PRIMARY);
+        assert_eq!(options.gpu.present_mode, wgpu::PresentMode::AutoVsync);
+        assert_eq!(options.target_fps, None);
+        assert_eq!(options.tick_rate, 60.0);
+        assert!(options.packs.is_empty());
+        assert!(!options.list_adapters && !options.help);
+    }
+
+    #[test]
+    fn every_option_is_parsed() {
+        let options = parse(&[
+            "--backend",
+            "Vulkan,gl",
+            "--power",
+            "high",
+            "--fallback",
+            "--feature",
+            "polygon_mode_line",
+            "--present-mode",
+            "mailbox",
+            "--fps",
+            "30",
+            "--tick-rate",
+            "120",
+            "--trace",
+            "5",
+            "--scene",
+            "scene.ron",
+            "--assets",
+            "data",
+            "--pack",
+            "a.pak",
+            "--pack",
+            "b.pak",
+            "--cache",
+            "cache",
+            "--list-adapters",
+            "-h",
+        ])
+        .unwrap();
+
+        assert_eq!(
+            options.gpu.backends,
+            wgpu::Backends::VULKAN | wgpu::Backends::GL
+        );
+        assert_eq!(
+            options.gpu.power_preference,
+            wgpu::PowerPreference::HighPerformance
+        );
+        assert!(options.gpu.force_fallback_adapter);
+        assert!(options
+            .gpu
+            .optional_features
+            .contains(wgpu::Features::POLYGON_MODE_LINE | wgpu::Features::TIMESTAMP_QUERY));
+        assert_eq!(options.gpu.present_mode, wgpu::PresentMode::Mailbox);
+        assert_eq!(options.target_fps, Some(30.0));
+        assert_eq!(options.tick_rate, 120.0);
+        assert_eq!(options.trace_frames, Some(5));
+        assert_eq!(options.scene, Some(PathBuf::from("scene.ron")));
+        assert_eq!(options.assets, Some(PathBuf::from("data")));
+        assert_eq!(
+            options.packs,
+            [PathBuf::from("a.pak"), PathBuf::from("b.pak")]
+        );
+        assert_eq!(options.cache, Some(PathBuf::from("cache")));
+        assert!(options.list_adapters && options.help);
+    }
+
+    #[test]
+    fn backend_shorthands_are_accepted() {
+        let backends = |name| parse(&["--backend", name]).unwrap().gpu.backends;
+        assert_eq!(backends("primary"), wgpu::Backends::PRIMARY);
+        assert_eq!(backends("ALL"), wgpu::Backends::all());
+    }
+
+    #[test]
+    fn unknown_options_are_errors() {
+        assert_eq!(error(&["--frobnicate"]), "unknown option --frobnicate");
+        assert_eq!(error(&["scene.ron"]), "unknown option scene.ron");
+    }
+
+    #[test]
+    fn missing_values_are_errors() {
+        for option in [
+            "--backend",
+            "--power",
+            "--feature",
+            "--present-mode",
+            "--fps",
+            "--tick-rate",
+            "--trace",
+            "--scene",
+            "--assets",
+            "--pack",
+            "--cache",
+        ] {
+            assert_eq!(error(&[option]), format!("{option} needs a value"));
+        }
+    }
+
+    #[test]
+    fn bad_values_are_errors() {
+        assert_eq!(error(&["--backend", "glide"]), "no known backend in glide");
+        assert!(error(&["--power", "max"]).starts_with("unknown power preference max"));
+        assert_eq!(
+            error(&["--feature", "warp_drive"]),
+            "unknown feature warp_drive"
+        );
+        assert_eq!(
+            error(&["--present-mode", "vsync"]),
+            "unknown present mode vsync"
+        );
+    }
+
+    #[test]
+    fn rates_and_counts_must_be_positive() {
+        for rate in ["0", "-5", "fast", "inf", "NaN"] {
+            assert!(parse(&["--fps", rate]).is_err(), "--fps {rate}");
+            assert!(parse(&["--tick-rate", rate]).is_err(), "--tick-rate {rate}");
+        }
+        for count in ["0", "-1", "2.5", "many"] {
+            assert!(parse(&["--trace", count]).is_err(), "--trace {count}");
+        }
+    }
+}
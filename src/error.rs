@@ -0,0 +1,40 @@
+use thiserror::Error;
+
+/// Everything that can go wrong while bringing up the window and the GPU.
+#[derive(Debug, Error)]
+pub enum RendererError {
+    #[error("{0}, see --help")]
+    Arguments(String),
+    #[error("could not create the event loop: {0}")]
+    EventLoop(#[from] winit::error::EventLoopError),
+    #[error("could not create a window: {0}")]
+    Window(#[from] winit::error::OsError),
+    #[error("could not create a surface for the window: {0}")]
+    Surface(#[from] wgpu::CreateSurfaceError),
+    #[error("no suitable graphics adapter found, try --list-adapters, --backend gl or --fallback")]
+    NoAdapter,
+    #[error("{adapter} does not support {missing:?}, try another --backend or --fallback")]
+    Unsupported {
+        adapter: String,
+        missing: wgpu::DownlevelFlags,
+    },
+    #[error("the graphics adapter could not create a device: {0}")]
+    Device(#[from] wgpu::RequestDeviceError),
+    #[error("rendering stopped: {0}")]
+    Frame(#[from] wgpu::SurfaceError),
+    #[error("failed to load {name}: {source:#}")]
+    Asset {
+        name: String,
+        #[source]
+        source: anyhow::Error,
+    },
+}
+
+impl RendererError {
+    pub fn asset(name: &str) -> impl FnOnce(anyhow::Error) -> Self + '_ {
+        move |source| Self::Asset {
+            name: name.to_string(),
+            source,
+        }
+    }
+}
@@ -0,0 +1,320 @@
+use std::time::{Duration, Instant};
+
+/// What the event loop should do after trying to render a frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameOutcome {
+    Presented,
+    // The surface was reconfigured, the next redraw will try again
+    Reconfigured,
+    // The frame was dropped, the surface itself is still usable
+    Skipped,
+    // Rendering cannot continue, shut down
+    Exit(wgpu::SurfaceError),
+}
+
+/// Something owning a surface that can be configured again for its current size.
+pub trait SurfaceTarget {
+    fn reconfigure(&mut self);
+}
+
+/// Recovers from surface errors returned by `State::render`. A surface that fails the same way
+/// frame after frame, such as a stalled one, is only reported once until a frame is presented.
+#[derive(Default)]
+pub struct FrameErrors {
+    last: Option<wgpu::SurfaceError>,
+}
+
+impl FrameErrors {
+    pub fn handle(
+        &mut self,
+        result: Result<(), wgpu::SurfaceError>,
+        target: &mut impl SurfaceTarget,
+    ) -> FrameOutcome {
+        let error = match result {
+            Ok(()) => {
+                if self.last.take().is_some() {
+                    log::info!("Presenting frames again");
+                }
+                return FrameOutcome::Presented;
+            }
+            Err(error) => error,
+        };
+        let changed = self.last.as_ref() != Some(&error);
+        self.last = Some(error.clone());
+
+        match error {
+            // The window changed or the swap chain was dropped, so configure it again with the current size
+            wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated => {
+                if changed {
+                    log::warn!("Surface lost or outdated, reconfiguring");
+                }
+                target.reconfigure();
+                FrameOutcome::Reconfigured
+            }
+            // The compositor did not hand out a texture in time, try again next frame
+            wgpu::SurfaceError::Timeout => {
+                if changed {
+                    log::warn!("Surface timed out, skipping frames");
+                }
+                FrameOutcome::Skipped
+            }
+            wgpu::SurfaceError::OutOfMemory => {
+                log::error!("Out of memory while acquiring a frame, shutting down");
+                FrameOutcome::Exit(error)
+            }
+            wgpu::SurfaceError::Other => {
+                if changed {
+                    log::warn!("Failed to acquire a frame: {error}, skipping frames");
+                }
+                FrameOutcome::Skipped
+            }
+        }
+    }
+}
+
+/// Spaces frames out to a target rate. Without a target frames are only paced by presenting.
+pub struct FrameLimiter {
+    interval: Option<Duration>,
+    next_frame: Option<Instant>,
+}
+
+impl FrameLimiter {
+    pub fn new(target_fps: Option<f64>) -> Self {
+        Self {
+            interval: target_fps.map(|fps| Duration::from_secs_f64(1.0 / fps)),
+            next_frame: None,
+        }
+    }
+
+    /// When the next frame should start, `None` if it can start right away.
+    pub fn deadline(&self, now: Instant) -> Option<Instant> {
+        self.next_frame.filter(|&next_frame| next_frame > now)
+    }
+
+    pub fn frame_started(&mut self, now: Instant) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        // Step from the previous deadline so the rate doesn't drift with wake up latency,
+        // but don't rush to catch up after a hitch
+        let next_frame = self.next_frame.unwrap_or(now) + interval;
+        self.next_frame = Some(if next_frame < now {
+            now + interval
+        } else {
+            next_frame
+        });
+    }
+}
+
+// Longest frame the simulation catches up on, anything beyond is dropped so a long
+// stall doesn't turn into a burst of updates that takes even longer
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+/// Accumulates frame time and splits it into fixed simulation steps.
+pub struct FixedTimestep {
+    step: Duration,
+    accumulator: Duration,
+    last_frame: Option<Instant>,
+}
+
+impl FixedTimestep {
+    pub fn new(rate_hz: f64) -> Self {
+        Self {
+            step: Duration::from_secs_f64(1.0 / rate_hz),
+            accumulator: Duration::ZERO,
+            last_frame: None,
+        }
+    }
+
+    /// Adds the time since the previous frame and returns how many steps to simulate.
+    pub fn advance(&mut self, now: Instant) -> u32 {
+        let elapsed = self.last_frame.map_or(Duration::ZERO, |last_frame| {
+            now.saturating_duration_since(last_frame)
+        });
+        self.last_frame = Some(now);
+
+        self.accumulator += elapsed.min(MAX_FRAME_TIME);
+        let steps = (self.accumulator.as_nanos() / self.step.as_nanos()) as u32;
+        self.accumulator -= self.step * steps;
+        steps
+    }
+
+    /// How far the frame is between the previous and the next step, from 0 to 1.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.step.as_secs_f32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Stands in for State, counting how often the surface was configured again
+    #[derive(Default)]
+    struct FakeSurface {
+        reconfigured: u32,
+    }
+    impl SurfaceTarget for FakeSurface {
+        fn reconfigure(&mut self) {
+            self.reconfigured += 1;
+        }
+    }
+
+    fn inject(result: Result<(), wgpu::SurfaceError>) -> (FrameOutcome, u32) {
+        let mut surface = FakeSurface::default();
+        let outcome = FrameErrors::default().handle(result, &mut surface);
+        (outcome, surface.reconfigured)
+    }
+
+    #[test]
+    fn presented_frame_needs_nothing() {
+        assert_eq!(inject(Ok(())), (FrameOutcome::Presented, 0));
+    }
+
+    #[test]
+    fn lost_surface_is_reconfigured() {
+        assert_eq!(
+            inject(Err(wgpu::SurfaceError::Lost)),
+            (FrameOutcome::Reconfigured, 1)
+        );
+    }
+
+    #[test]
+    fn outdated_surface_is_reconfigured() {
+        assert_eq!(
+            inject(Err(wgpu::SurfaceError::Outdated)),
+            (FrameOutcome::Reconfigured, 1)
+        );
+    }
+
+    #[test]
+    fn timeout_skips_the_frame() {
+        assert_eq!(
+            inject(Err(wgpu::SurfaceError::Timeout)),
+            (FrameOutcome::Skipped, 0)
+        );
+    }
+
+    #[test]
+    fn other_error_skips_the_frame() {
+        assert_eq!(
+            inject(Err(wgpu::SurfaceError::Other)),
+            (FrameOutcome::Skipped, 0)
+        );
+    }
+
+    #[test]
+    fn out_of_memory_exits() {
+        assert_eq!(
+            inject(Err(wgpu::SurfaceError::OutOfMemory)),
+            (FrameOutcome::Exit(wgpu::SurfaceError::OutOfMemory), 0)
+        );
+    }
+
+    #[test]
+    fn repeated_errors_are_remembered_until_a_frame_is_presented() {
+        let mut errors = FrameErrors::default();
+        let mut surface = FakeSurface::default();
+        for _ in 0..3 {
+            let outcome = errors.handle(Err(wgpu::SurfaceError::Outdated), &mut surface);
+            assert_eq!(outcome, FrameOutcome::Reconfigured);
+        }
+        // Every failed frame is still recovered from, only the report is not repeated
+        assert_eq!(surface.reconfigured, 3);
+        assert_eq!(errors.last, Some(wgpu::SurfaceError::Outdated));
+
+        errors.handle(Ok(()), &mut surface);
+        assert_eq!(errors.last, None);
+    }
+
+    #[test]
+    fn unlimited_frames_never_wait() {
+        let mut limiter = FrameLimiter::new(None);
+        let now = Instant::now();
+        limiter.frame_started(now);
+        assert_eq!(limiter.deadline(now), None);
+    }
+
+    #[test]
+    fn limited_frames_wait_for_the_interval() {
+        let mut limiter = FrameLimiter::new(Some(50.0));
+        let start = Instant::now();
+        assert_eq!(limiter.deadline(start), None);
+
+        limiter.frame_started(start);
+        assert_eq!(
+            limiter.deadline(start),
+            Some(start + Duration::from_millis(20))
+        );
+        assert_eq!(limiter.deadline(start + Duration::from_millis(20)), None);
+    }
+
+    #[test]
+    fn late_frames_keep_a_steady_rate() {
+        let mut limiter = FrameLimiter::new(Some(50.0));
+        let start = Instant::now();
+        limiter.frame_started(start);
+        // Woken up a little late, the next deadline still lines up with the first frame
+        limiter.frame_started(start + Duration::from_millis(21));
+        assert_eq!(
+            limiter.deadline(start),
+            Some(start + Duration::from_millis(40))
+        );
+    }
+
+    #[test]
+    fn hitches_are_not_caught_up() {
+        let mut limiter = FrameLimiter::new(Some(50.0));
+        let start = Instant::now();
+        limiter.frame_started(start);
+        let late = start + Duration::from_millis(500);
+        limiter.frame_started(late);
+        assert_eq!(
+            limiter.deadline(late),
+            Some(late + Duration::from_millis(20))
+        );
+    }
+
+    #[test]
+    fn first_frame_runs_no_steps() {
+        let mut timestep = FixedTimestep::new(60.0);
+        assert_eq!(timestep.advance(Instant::now()), 0);
+        assert_eq!(timestep.alpha(), 0.0);
+    }
+
+    #[test]
+    fn steps_do_not_depend_on_the_frame_rate() {
+        let start = Instant::now();
+        let mut slow = FixedTimestep::new(100.0);
+        let mut fast = FixedTimestep::new(100.0);
+        slow.advance(start);
+        fast.advance(start);
+
+        // One second at 25 fps and at 250 fps
+        let slow_steps: u32 = (1..=25)
+            .map(|frame| slow.advance(start + Duration::from_millis(40 * frame)))
+            .sum();
+        let fast_steps: u32 = (1..=250)
+            .map(|frame| fast.advance(start + Duration::from_millis(4 * frame)))
+            .sum();
+        assert_eq!(slow_steps, 100);
+        assert_eq!(fast_steps, 100);
+    }
+
+    #[test]
+    fn leftover_time_becomes_alpha() {
+        let start = Instant::now();
+        let mut timestep = FixedTimestep::new(100.0);
+        timestep.advance(start);
+        assert_eq!(timestep.advance(start + Duration::from_millis(25)), 2);
+        assert!((timestep.alpha() - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn long_stalls_are_capped() {
+        let start = Instant::now();
+        let mut timestep = FixedTimestep::new(100.0);
+        timestep.advance(start);
+        assert_eq!(timestep.advance(start + Duration::from_secs(10)), 25);
+    }
+}
@@ -0,0 +1,364 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use pollster::FutureExt;
+use winit::dpi::PhysicalSize;
+use winit::window::Window;
+
+use crate::config::GpuConfig;
+use crate::RendererError;
+
+// Point light shadows keep one cube per light in a single cube array, which WebGL2 and
+// some GLES drivers cannot sample
+const REQUIRED_DOWNLEVEL: wgpu::DownlevelFlags = wgpu::DownlevelFlags::CUBE_ARRAY_TEXTURES;
+
+/// The adapter, device and surface. Everything here is thrown away and created
+/// again when the device is lost.
+pub struct Gpu {
+    pub surface: wgpu::Surface<'static>,
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    pub config: wgpu::SurfaceConfiguration,
+    present_modes: Vec<wgpu::PresentMode>,
+    // Set from the device lost callback, which may run on another thread
+    lost: Arc<AtomicBool>,
+}
+
+impl Gpu {
+    pub fn new(window: Arc<Window>, gpu_config: &GpuConfig) -> Result<Self, RendererError> {
+        let size = window.inner_size();
+        let instance = Self::create_gpu_instance(gpu_config);
+        let surface = instance.create_surface(window)?;
+        let adapter = Self::create_adapter(instance, &surface, gpu_config)?;
+        let missing = REQUIRED_DOWNLEVEL - adapter.get_downlevel_capabilities().flags;
+        if !missing.is_empty() {
+            return Err(RendererError::Unsupported {
+                adapter: adapter.get_info().name,
+                missing,
+            });
+        }
+        let (device, queue) = Self::create_device(&adapter, gpu_config)?;
+        log_adapter_info(&adapter, &device);
+        let surface_caps = surface.get_capabilities(&adapter);
+        let max_dimension = device.limits().max_texture_dimension_2d;
+        let surface_size = surface_size(size, max_dimension);
+        let present_modes = surface_caps.present_modes.clone();
+        // A window created minimized gets configured on its first resize instead
+        let config = Self::create_surface_config(
+            surface_caps,
+            surface_size.unwrap_or(PhysicalSize::new(1, 1)),
+            gpu_config.present_mode,
+        );
+        log::info!("Present mode: {:?}", config.present_mode);
+        if surface_size.is_some() {
+            surface.configure(&device, &config);
+        }
+
+        let lost = Arc::new(AtomicBool::new(false));
+        Self::watch_device(&device, lost.clone());
+
+        Ok(Self {
+            surface,
+            device,
+            queue,
+            config,
+            present_modes,
+            lost,
+        })
+    }
+
+    /// True once the driver reported the device as lost. Nothing created on it can be used anymore.
+    pub fn is_lost(&self) -> bool {
+        self.lost.load(Ordering::Acquire)
+    }
+
+    /// Configures the surface for a new window size. Returns false and leaves the surface
+    /// alone while the window is minimized.
+    pub fn resize(&mut self, size: PhysicalSize<u32>) -> bool {
+        let max_dimension = self.device.limits().max_texture_dimension_2d;
+        let Some(size) = surface_size(size, max_dimension) else {
+            return false;
+        };
+        self.config.width = size.width;
+        self.config.height = size.height;
+        self.surface.configure(&self.device, &self.config);
+        true
+    }
+
+    /// Switches to the supported mode closest to `requested`. Takes effect the next time the
+    /// surface is configured.
+    pub fn set_present_mode(&mut self, requested: wgpu::PresentMode) -> wgpu::PresentMode {
+        self.config.present_mode = choose_present_mode(requested, &self.present_modes);
+        self.config.present_mode
+    }
+
+    fn watch_device(device: &wgpu::Device, lost: Arc<AtomicBool>) {
+        device.set_device_lost_callback(move |reason, message| {
+            log::error!("GPU device lost ({reason:?}): {message}");
+            lost.store(true, Ordering::Release);
+        });
+
+        // The default handler panics. Work recorded against a lost device fails
+        // validation until we get around to recreating it, so only log instead
+        device.on_uncaptured_error(Box::new(|error| {
+            log::error!("wgpu error: {error}");
+        }));
+    }
+
+    fn create_gpu_instance(gpu_config: &GpuConfig) -> wgpu::Instance {
+        wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: gpu_config.backends,
+            ..Default::default()
+        })
+    }
+
+    fn create_adapter(
+        instance: wgpu::Instance,
+        surface: &wgpu::Surface,
+        gpu_config: &GpuConfig,
+    ) -> Result<wgpu::Adapter, RendererError> {
+        instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: gpu_config.power_preference,
+                compatible_surface: Some(surface),
+                force_fallback_adapter: gpu_config.force_fallback_adapter,
+            })
+            .block_on()
+            .ok_or(RendererError::NoAdapter)
+    }
+
+    fn create_device(
+        adapter: &wgpu::Adapter,
+        gpu_config: &GpuConfig,
+    ) -> Result<(wgpu::Device, wgpu::Queue), RendererError> {
+        let missing = gpu_config.optional_features - adapter.features();
+        if !missing.is_empty() {
+            log::warn!("Adapter does not support {missing:?}, continuing without");
+        }
+
+        let features = gpu_config.optional_features & adapter.features();
+        let mut limits = negotiate_limits(&adapter.limits());
+        // Every texture of a binding array counts against the per stage limit
+        if features.contains(wgpu::Features::TEXTURE_BINDING_ARRAY) {
+            limits.max_sampled_textures_per_shader_stage =
+                adapter.limits().max_sampled_textures_per_shader_stage;
+        }
+
+        let device = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    required_features: features,
+                    required_limits: limits,
+                    label: None,
+                    memory_hints: Default::default(),
+                },
+                None, // Trace path
+            )
+            .block_on()?;
+        Ok(device)
+    }
+
+    fn create_surface_config(
+        surface_caps: wgpu::SurfaceCapabilities,
+        size: PhysicalSize<u32>,
+        present_mode: wgpu::PresentMode,
+    ) -> wgpu::SurfaceConfiguration {
+        // find surface format with sRGB
+        let surface_format = surface_caps
+            .formats
+            .iter()
+            .find(|f| f.is_srgb())
+            .copied()
+            .unwrap_or(surface_caps.formats[0]);
+        wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: surface_format,
+            width: size.width,
+            height: size.height,
+            present_mode: choose_present_mode(present_mode, &surface_caps.present_modes),
+            // Lets wgpu pick opaque where the compositor supports it
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        }
+    }
+}
+
+/// Picks `requested` if the surface supports it, otherwise the closest mode that it does.
+/// Fifo is the only mode every surface supports, so it is the last resort.
+pub fn choose_present_mode(
+    requested: wgpu::PresentMode,
+    supported: &[wgpu::PresentMode],
+) -> wgpu::PresentMode {
+    use wgpu::PresentMode::*;
+
+    let preferences: &[wgpu::PresentMode] = match requested {
+        AutoVsync | FifoRelaxed => &[FifoRelaxed, Fifo],
+        AutoNoVsync | Immediate => &[Immediate, Mailbox, Fifo],
+        Mailbox => &[Mailbox, Immediate, Fifo],
+        Fifo => &[Fifo],
+    };
+    preferences
+        .iter()
+        .copied()
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(Fifo)
+}
+
+/// Size to configure the surface with, or `None` while the window has no area, e.g. when
+/// minimized. The surface may not be larger than the biggest texture the device supports,
+/// the compositor stretches it over the rest of the window.
+pub fn surface_size(
+    window_size: PhysicalSize<u32>,
+    max_dimension: u32,
+) -> Option<PhysicalSize<u32>> {
+    if window_size.width == 0 || window_size.height == 0 {
+        return None;
+    }
+    Some(PhysicalSize::new(
+        window_size.width.min(max_dimension),
+        window_size.height.min(max_dimension),
+    ))
+}
+
+/// Width over height of the window, 1 if it has no height.
+pub fn aspect_ratio(window_size: PhysicalSize<u32>) -> f32 {
+    if window_size.height == 0 {
+        return 1.0;
+    }
+    window_size.width as f32 / window_size.height as f32
+}
+
+// Asks for wgpu's default limits where the adapter has them and the downlevel ones
+// otherwise, e.g. on GL. Texture sizes always go up to what the adapter allows, so
+// large windows still get a depth buffer.
+fn negotiate_limits(supported: &wgpu::Limits) -> wgpu::Limits {
+    let limits = if wgpu::Limits::default().check_limits(supported) {
+        wgpu::Limits::default()
+    } else {
+        wgpu::Limits::downlevel_defaults()
+    };
+    limits.using_resolution(supported.clone())
+}
+
+fn log_adapter_info(adapter: &wgpu::Adapter, device: &wgpu::Device) {
+    let info = adapter.get_info();
+    log::info!(
+        "Using {} ({:?}, {:?})",
+        info.name,
+        info.backend,
+        info.device_type
+    );
+    if !info.driver.is_empty() {
+        log::info!("Driver: {} {}", info.driver, info.driver_info);
+    }
+    log::info!("Enabled features: {:?}", device.features());
+    log::info!(
+        "Max texture size: {}",
+        device.limits().max_texture_dimension_2d
+    );
+}
+
+/// Prints every adapter the selected backends expose, for `--list-adapters`.
+pub fn list_adapters(gpu_config: &GpuConfig) {
+    let instance = Gpu::create_gpu_instance(gpu_config);
+    let adapters = instance.enumerate_adapters(gpu_config.backends);
+    if adapters.is_empty() {
+        println!("No adapters found for {:?}", gpu_config.backends);
+    }
+    for (index, adapter) in adapters.iter().enumerate() {
+        let info = adapter.get_info();
+        println!(
+            "{index}: {} ({:?}, {:?})",
+            info.name, info.backend, info.device_type
+        );
+        if !info.driver.is_empty() {
+            println!("   driver: {} {}", info.driver, info.driver_info);
+        }
+        println!(
+            "   max texture size: {}",
+            adapter.limits().max_texture_dimension_2d
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supported_present_mode_is_kept() {
+        use wgpu::PresentMode::*;
+        let supported = [Fifo, Mailbox, Immediate];
+        assert_eq!(choose_present_mode(Mailbox, &supported), Mailbox);
+        assert_eq!(choose_present_mode(Immediate, &supported), Immediate);
+        assert_eq!(choose_present_mode(Fifo, &supported), Fifo);
+    }
+
+    #[test]
+    fn unsupported_present_mode_falls_back() {
+        use wgpu::PresentMode::*;
+        assert_eq!(choose_present_mode(Mailbox, &[Fifo, Immediate]), Immediate);
+        assert_eq!(choose_present_mode(Immediate, &[Fifo, Mailbox]), Mailbox);
+        assert_eq!(choose_present_mode(Immediate, &[Fifo]), Fifo);
+        assert_eq!(choose_present_mode(FifoRelaxed, &[Fifo]), Fifo);
+        // Even a surface reporting nothing gets a valid mode
+        assert_eq!(choose_present_mode(Mailbox, &[]), Fifo);
+    }
+
+    #[test]
+    fn auto_present_modes_resolve_to_concrete_modes() {
+        use wgpu::PresentMode::*;
+        assert_eq!(
+            choose_present_mode(AutoVsync, &[Fifo, FifoRelaxed]),
+            FifoRelaxed
+        );
+        assert_eq!(choose_present_mode(AutoVsync, &[Fifo, Immediate]), Fifo);
+        assert_eq!(choose_present_mode(AutoNoVsync, &[Fifo, Mailbox]), Mailbox);
+        assert_eq!(choose_present_mode(AutoNoVsync, &[Fifo]), Fifo);
+    }
+
+    #[test]
+    fn minimized_window_has_no_surface_size() {
+        assert_eq!(surface_size(PhysicalSize::new(0, 0), 8192), None);
+        assert_eq!(surface_size(PhysicalSize::new(800, 0), 8192), None);
+        assert_eq!(surface_size(PhysicalSize::new(0, 600), 8192), None);
+    }
+
+    #[test]
+    fn surface_size_within_limits_is_unchanged() {
+        assert_eq!(
+            surface_size(PhysicalSize::new(800, 600), 8192),
+            Some(PhysicalSize::new(800, 600))
+        );
+        assert_eq!(
+            surface_size(PhysicalSize::new(1, 1), 8192),
+            Some(PhysicalSize::new(1, 1))
+        );
+    }
+
+    #[test]
+    fn surface_size_is_clamped_to_max_texture_dimension() {
+        assert_eq!(
+            surface_size(PhysicalSize::new(10000, 600), 8192),
+            Some(PhysicalSize::new(8192, 600))
+        );
+        assert_eq!(
+            surface_size(PhysicalSize::new(20000, 30000), 2048),
+            Some(PhysicalSize::new(2048, 2048))
+        );
+    }
+
+    #[test]
+    fn aspect_ratio_is_not_truncated() {
+        assert_eq!(aspect_ratio(PhysicalSize::new(1280, 720)), 1280.0 / 720.0);
+        // Integer division used to turn portrait windows into an aspect of 0
+        assert_eq!(aspect_ratio(PhysicalSize::new(600, 800)), 0.75);
+    }
+
+    #[test]
+    fn aspect_ratio_of_zero_height_is_finite() {
+        assert_eq!(aspect_ratio(PhysicalSize::new(800, 0)), 1.0);
+        assert_eq!(aspect_ratio(PhysicalSize::new(0, 0)), 1.0);
+    }
+}
@@ -0,0 +1,279 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::stats::PassTiming;
+
+// Timed passes per frame, two timestamps each
+const MAX_PASSES: u32 = 64;
+// Frames whose timestamps can be waiting to be read back at once
+const READBACK_COUNT: usize = 3;
+
+struct Readback {
+    buffer: wgpu::Buffer,
+    // Passes whose timestamps the buffer holds, empty while it is free
+    passes: Vec<&'static str>,
+    frame: u64,
+    submitted: Instant,
+    mapped: Arc<AtomicBool>,
+}
+
+/// One pass of a [`GpuFrame`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GpuPass {
+    pub name: &'static str,
+    // Since the first pass of the frame began
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+/// GPU timings of the passes of one frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GpuFrame {
+    // When the frame was submitted, the GPU starts on it some time later
+    pub submitted: Instant,
+    // In the order they were recorded
+    pub passes: Vec<GpuPass>,
+}
+
+impl GpuFrame {
+    fn from_timestamps(
+        names: &[&'static str],
+        timestamps: &[u64],
+        period: f64,
+        submitted: Instant,
+    ) -> Self {
+        let to_duration = |ticks: u64| Duration::from_nanos((ticks as f64 * period) as u64);
+        let first = timestamps.iter().step_by(2).min().copied().unwrap_or(0);
+        let passes = names
+            .iter()
+            .zip(timestamps.chunks_exact(2))
+            .map(|(&name, pair)| GpuPass {
+                name,
+                start: to_duration(pair[0].saturating_sub(first)),
+                // Some drivers report an end before the beginning for very short passes
+                duration: to_duration(pair[1].saturating_sub(pair[0])),
+            })
+            .collect();
+        Self { submitted, passes }
+    }
+
+    /// Time per pass name, adding up the passes sharing one, in the order they were first recorded.
+    pub fn timings(&self) -> Vec<PassTiming> {
+        let mut timings: Vec<PassTiming> = Vec::new();
+        for pass in &self.passes {
+            match timings.iter_mut().find(|timing| timing.name == pass.name) {
+                Some(timing) => timing.duration += pass.duration,
+                None => timings.push(PassTiming {
+                    name: pass.name,
+                    duration: pass.duration,
+                }),
+            }
+        }
+        timings
+    }
+}
+
+/// Measures how long render passes take on the GPU with timestamp queries.
+/// Results are read back a few frames later without waiting on the GPU.
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readbacks: Vec<Readback>,
+    // Nanoseconds per timestamp tick
+    period: f64,
+    // Passes recorded so far this frame, pass i writes queries 2i and 2i + 1
+    passes: Vec<&'static str>,
+    // Readback copied to in the frame being submitted, mapped once it is
+    pending: Option<usize>,
+    frame: u64,
+}
+
+impl GpuTimer {
+    /// Returns `None` when the device was created without `TIMESTAMP_QUERY`.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("gpu_timer_query_set"),
+            ty: wgpu::QueryType::Timestamp,
+            count: MAX_PASSES * 2,
+        });
+
+        let size =
+            (MAX_PASSES * 2) as wgpu::BufferAddress * wgpu::QUERY_SIZE as wgpu::BufferAddress;
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("gpu_timer_resolve_buffer"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readbacks = (0..READBACK_COUNT)
+            .map(|_| Readback {
+                buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("gpu_timer_readback_buffer"),
+                    size,
+                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                passes: Vec::new(),
+                frame: 0,
+                submitted: Instant::now(),
+                mapped: Arc::new(AtomicBool::new(false)),
+            })
+            .collect();
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readbacks,
+            period: queue.get_timestamp_period() as f64,
+            passes: Vec::new(),
+            pending: None,
+            frame: 0,
+        })
+    }
+
+    /// Timestamp writes for a pass named `name`. Passes sharing a name are added up.
+    pub fn pass(&mut self, name: &'static str) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        let index = self.passes.len() as u32;
+        if index == MAX_PASSES {
+            return None;
+        }
+        self.passes.push(name);
+        Some(wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(index * 2),
+            end_of_pass_write_index: Some(index * 2 + 1),
+        })
+    }
+
+    /// Resolves this frame's timestamps into a free readback buffer. The frame goes
+    /// untimed when all of them are still waiting on the GPU.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        self.frame += 1;
+        let passes = std::mem::take(&mut self.passes);
+        let Some(index) = self.readbacks.iter().position(|r| r.passes.is_empty()) else {
+            return;
+        };
+        if passes.is_empty() {
+            return;
+        }
+
+        let query_count = passes.len() as u32 * 2;
+        let size = query_count as wgpu::BufferAddress * wgpu::QUERY_SIZE as wgpu::BufferAddress;
+        encoder.resolve_query_set(&self.query_set, 0..query_count, &self.resolve_buffer, 0);
+        let readback = &mut self.readbacks[index];
+        encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, &readback.buffer, 0, size);
+        readback.passes = passes;
+        readback.frame = self.frame;
+        self.pending = Some(index);
+    }
+
+    /// Starts reading back the timestamps resolved this frame, call after submitting it.
+    pub fn map(&mut self) {
+        let Some(index) = self.pending.take() else {
+            return;
+        };
+        let readback = &mut self.readbacks[index];
+        readback.submitted = Instant::now();
+        let mapped = readback.mapped.clone();
+        readback
+            .buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                if result.is_ok() {
+                    mapped.store(true, Ordering::Release);
+                }
+            });
+    }
+
+    /// The newest frame whose timestamps have arrived since the last call.
+    pub fn collect(&mut self, device: &wgpu::Device) -> Option<GpuFrame> {
+        device.poll(wgpu::Maintain::Poll);
+
+        let mut newest: Option<(u64, GpuFrame)> = None;
+        for readback in &mut self.readbacks {
+            if !readback.mapped.swap(false, Ordering::Acquire) {
+                continue;
+            }
+
+            let passes = std::mem::take(&mut readback.passes);
+            let timestamps = {
+                let range = readback.buffer.slice(..).get_mapped_range();
+                bytemuck::cast_slice::<u8, u64>(&range)[..passes.len() * 2].to_vec()
+            };
+            readback.buffer.unmap();
+
+            if newest
+                .as_ref()
+                .is_some_and(|(frame, _)| *frame > readback.frame)
+            {
+                continue;
+            }
+            let frame =
+                GpuFrame::from_timestamps(&passes, &timestamps, self.period, readback.submitted);
+            newest = Some((readback.frame, frame));
+        }
+
+        newest.map(|(_, frame)| frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_start_at_the_first_timestamp() {
+        let submitted = Instant::now();
+        let frame =
+            GpuFrame::from_timestamps(&["shadows", "scene"], &[100, 200, 300, 600], 2.0, submitted);
+        assert_eq!(
+            frame.passes,
+            vec![
+                GpuPass {
+                    name: "shadows",
+                    start: Duration::ZERO,
+                    duration: Duration::from_nanos(200),
+                },
+                GpuPass {
+                    name: "scene",
+                    start: Duration::from_nanos(400),
+                    duration: Duration::from_nanos(600),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn passes_with_the_same_name_are_added_up() {
+        let frame = GpuFrame::from_timestamps(
+            &["shadows", "shadows", "scene"],
+            &[100, 200, 300, 350, 400, 1000],
+            2.0,
+            Instant::now(),
+        );
+        assert_eq!(
+            frame.timings(),
+            vec![
+                PassTiming {
+                    name: "shadows",
+                    duration: Duration::from_nanos(300),
+                },
+                PassTiming {
+                    name: "scene",
+                    duration: Duration::from_nanos(1200),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn end_before_beginning_counts_as_zero() {
+        let frame = GpuFrame::from_timestamps(&["scene"], &[500, 400], 1.0, Instant::now());
+        assert_eq!(frame.passes[0].duration, Duration::ZERO);
+    }
+}
@@ -0,0 +1,356 @@
+use cgmath::{Matrix4, One, Quaternion, SquareMatrix, Vector3, VectorSpace, Zero};
+
+use crate::light::PointLight;
+
+/// Index of a node in its [`SceneGraph`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+/// Translation, rotation and scale of a node relative to its parent.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::zero(),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+impl Transform {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    /// Blends from `previous` towards this transform, `alpha` 0 is `previous` and 1 is `self`.
+    pub fn interpolate(&self, previous: &Transform, alpha: f32) -> Transform {
+        Transform {
+            translation: previous.translation.lerp(self.translation, alpha),
+            rotation: previous.rotation.slerp(self.rotation, alpha),
+            scale: previous.scale.lerp(self.scale, alpha),
+        }
+    }
+}
+
+/// Draws the scene's mesh with a material at the node's transform.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MeshComponent {
+    // Index into Scene::materials
+    pub material: usize,
+}
+
+pub struct Node {
+    pub name: String,
+    local: Transform,
+    // Local transform before the last fixed update
+    previous: Transform,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    // World matrix of the current fixed update, stale while dirty
+    world: Matrix4<f32>,
+    // World matrix blended between the last two fixed updates, for rendering
+    interpolated: Matrix4<f32>,
+    dirty: bool,
+    pub mesh: Option<MeshComponent>,
+    // Position relative to the node
+    pub light: Option<PointLight>,
+    // The scene camera moves and looks relative to this node
+    pub camera: bool,
+}
+impl Node {
+    pub fn local(&self) -> &Transform {
+        &self.local
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+}
+
+/// Hierarchy of nodes with transforms relative to their parents. World matrices are
+/// only recomputed for the nodes whose transform, or an ancestor's, has changed.
+#[derive(Default)]
+pub struct SceneGraph {
+    nodes: Vec<Node>,
+}
+
+impl SceneGraph {
+    pub fn add(&mut self, name: &str, parent: Option<NodeId>, local: Transform) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node {
+            name: name.to_string(),
+            local,
+            previous: local,
+            parent,
+            children: Vec::new(),
+            world: Matrix4::identity(),
+            interpolated: Matrix4::identity(),
+            dirty: true,
+            mesh: None,
+            light: None,
+            camera: false,
+        });
+        if let Some(parent) = parent {
+            self.nodes[parent.0].children.push(id);
+        }
+        id
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    /// For changing components, the transform is changed through [`Self::local_mut`].
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| (NodeId(index), node))
+    }
+
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.nodes
+            .iter()
+            .position(|node| node.name == name)
+            .map(NodeId)
+    }
+
+    pub fn local_mut(&mut self, id: NodeId) -> &mut Transform {
+        let node = &mut self.nodes[id.0];
+        node.dirty = true;
+        &mut node.local
+    }
+
+    /// Moves a node, and with it its children, under another parent. The local transform
+    /// is kept, so the node jumps to the same place relative to its new parent. Returns
+    /// false and changes nothing when `parent` is the node itself or one of its descendants.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> bool {
+        let mut ancestor = parent;
+        while let Some(current) = ancestor {
+            if current == id {
+                return false;
+            }
+            ancestor = self.nodes[current.0].parent;
+        }
+
+        if let Some(old) = self.nodes[id.0].parent {
+            self.nodes[old.0].children.retain(|&child| child != id);
+        }
+        if let Some(parent) = parent {
+            self.nodes[parent.0].children.push(id);
+        }
+        let node = &mut self.nodes[id.0];
+        node.parent = parent;
+        node.dirty = true;
+        true
+    }
+
+    /// Remembers the local transforms before a fixed update changes them.
+    pub fn begin_step(&mut self) {
+        for node in &mut self.nodes {
+            node.previous = node.local;
+        }
+    }
+
+    /// Makes the node jump to its current local transform instead of blending towards it
+    /// from where it was before this update.
+    pub fn snap(&mut self, id: NodeId) {
+        let node = &mut self.nodes[id.0];
+        node.previous = node.local;
+    }
+
+    /// World matrix as of the last call to [`Self::update_world`] or [`Self::interpolate`].
+    #[cfg(test)]
+    pub fn world(&self, id: NodeId) -> Matrix4<f32> {
+        self.nodes[id.0].world
+    }
+
+    /// World matrix as of the last call to [`Self::interpolate`].
+    pub fn interpolated_world(&self, id: NodeId) -> Matrix4<f32> {
+        self.nodes[id.0].interpolated
+    }
+
+    /// Recomputes the world matrices of dirty nodes and their descendants.
+    pub fn update_world(&mut self) {
+        for root in self.roots() {
+            self.update_node(root, Matrix4::identity(), false);
+        }
+    }
+
+    fn update_node(&mut self, id: NodeId, parent_world: Matrix4<f32>, parent_changed: bool) {
+        let node = &mut self.nodes[id.0];
+        let changed = node.dirty || parent_changed;
+        if changed {
+            node.world = parent_world * node.local.matrix();
+            node.dirty = false;
+        }
+        let world = node.world;
+        for index in 0..self.nodes[id.0].children.len() {
+            let child = self.nodes[id.0].children[index];
+            self.update_node(child, world, changed);
+        }
+    }
+
+    /// Blends every node `alpha` of the way from the previous fixed update to the current
+    /// one. Nodes that did not move in the last update, nor did their ancestors, reuse
+    /// their world matrix.
+    pub fn interpolate(&mut self, alpha: f32) {
+        self.update_world();
+        for root in self.roots() {
+            self.interpolate_node(root, Matrix4::identity(), false, alpha);
+        }
+    }
+
+    fn interpolate_node(
+        &mut self,
+        id: NodeId,
+        parent_interpolated: Matrix4<f32>,
+        parent_moving: bool,
+        alpha: f32,
+    ) {
+        let node = &mut self.nodes[id.0];
+        let moving = parent_moving || node.local != node.previous;
+        node.interpolated = if moving {
+            parent_interpolated * node.local.interpolate(&node.previous, alpha).matrix()
+        } else {
+            node.world
+        };
+        let interpolated = node.interpolated;
+        for index in 0..self.nodes[id.0].children.len() {
+            let child = self.nodes[id.0].children[index];
+            self.interpolate_node(child, interpolated, moving, alpha);
+        }
+    }
+
+    fn roots(&self) -> Vec<NodeId> {
+        self.nodes()
+            .filter(|(_, node)| node.parent.is_none())
+            .map(|(id, _)| id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{InnerSpace, Rad, Rotation3};
+
+    fn origin_of(matrix: Matrix4<f32>) -> Vector3<f32> {
+        matrix.w.truncate()
+    }
+
+    fn at(x: f32, y: f32, z: f32) -> Transform {
+        Transform {
+            translation: Vector3::new(x, y, z),
+            ..Default::default()
+        }
+    }
+
+    fn assert_near(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-5, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn children_follow_their_parent() {
+        let mut graph = SceneGraph::default();
+        let parent = graph.add("parent", None, at(1.0, 0.0, 0.0));
+        let child = graph.add("child", Some(parent), at(0.0, 2.0, 0.0));
+        graph.update_world();
+        assert_near(origin_of(graph.world(child)), Vector3::new(1.0, 2.0, 0.0));
+
+        graph.local_mut(parent).rotation =
+            Quaternion::from_angle_z(Rad(std::f32::consts::FRAC_PI_2));
+        graph.update_world();
+        assert_near(origin_of(graph.world(child)), Vector3::new(-1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn only_dirty_subtrees_are_recomputed() {
+        let mut graph = SceneGraph::default();
+        let a = graph.add("a", None, Transform::default());
+        let b = graph.add("b", None, Transform::default());
+        graph.update_world();
+
+        // Overwrite a cached matrix, it must survive an update that does not touch it
+        let marker = Matrix4::from_scale(3.0);
+        graph.nodes[a.0].world = marker;
+        graph.local_mut(b).translation.x = 1.0;
+        graph.update_world();
+        assert_eq!(graph.world(a), marker);
+        assert_near(origin_of(graph.world(b)), Vector3::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn reparenting_keeps_the_local_transform() {
+        let mut graph = SceneGraph::default();
+        let a = graph.add("a", None, at(5.0, 0.0, 0.0));
+        let b = graph.add("b", None, at(0.0, 1.0, 0.0));
+        assert!(graph.set_parent(b, Some(a)));
+        graph.update_world();
+        assert_near(origin_of(graph.world(b)), Vector3::new(5.0, 1.0, 0.0));
+        assert_eq!(graph.node(a).children(), &[b]);
+    }
+
+    #[test]
+    fn cycles_are_refused() {
+        let mut graph = SceneGraph::default();
+        let a = graph.add("a", None, Transform::default());
+        let b = graph.add("b", Some(a), Transform::default());
+        assert!(!graph.set_parent(a, Some(b)));
+        assert!(!graph.set_parent(a, Some(a)));
+        assert_eq!(graph.node(a).parent(), None);
+    }
+
+    #[test]
+    fn interpolation_blends_moving_nodes_and_their_children() {
+        let mut graph = SceneGraph::default();
+        let parent = graph.add("parent", None, Transform::default());
+        let child = graph.add("child", Some(parent), at(0.0, 1.0, 0.0));
+        let still = graph.add("still", None, at(0.0, 0.0, 3.0));
+
+        graph.begin_step();
+        graph.local_mut(parent).translation.x = 2.0;
+        graph.interpolate(0.25);
+
+        assert_near(
+            origin_of(graph.interpolated_world(child)),
+            Vector3::new(0.5, 1.0, 0.0),
+        );
+        assert_near(
+            origin_of(graph.interpolated_world(still)),
+            Vector3::new(0.0, 0.0, 3.0),
+        );
+        assert_near(origin_of(graph.world(child)), Vector3::new(2.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn snapped_nodes_are_not_blended() {
+        let mut graph = SceneGraph::default();
+        let node = graph.add("node", None, at(10.0, 0.0, 0.0));
+
+        graph.begin_step();
+        graph.local_mut(node).translation.x = 0.0;
+        graph.snap(node);
+        graph.interpolate(0.25);
+
+        assert_near(
+            origin_of(graph.interpolated_world(node)),
+            Vector3::new(0.0, 0.0, 0.0),
+        );
+    }
+}
@@ -0,0 +1,260 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::Result;
+
+use crate::graph::SceneGraph;
+use crate::renderer::Renderer;
+use crate::scene::Scene;
+use crate::scene_file::{self, MaterialFile, SceneFile};
+use crate::vfs::Vfs;
+
+// How often the modification time of the scene file is looked at
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What a reload does with a material, by its index in the reloaded file.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MaterialUpdate {
+    // Unchanged, the material at this index of the current list is kept
+    Keep(usize),
+    // Only the factors changed, the current material's uniforms are rewritten
+    Params(usize),
+    // New, or its textures changed
+    Upload,
+}
+
+/// Matches the materials of the reloaded file to the current ones by name.
+pub fn plan_materials(current: &[MaterialFile], reloaded: &[MaterialFile]) -> Vec<MaterialUpdate> {
+    reloaded
+        .iter()
+        .map(|material| {
+            let Some(index) = current.iter().position(|old| old.name == material.name) else {
+                return MaterialUpdate::Upload;
+            };
+            let old = &current[index];
+            let same_textures = MaterialFile {
+                params: material.params,
+                ..old.clone()
+            } == *material;
+            if old == material {
+                MaterialUpdate::Keep(index)
+            } else if same_textures {
+                MaterialUpdate::Params(index)
+            } else {
+                MaterialUpdate::Upload
+            }
+        })
+        .collect()
+}
+
+/// Carries the live transforms of the nodes that are the same in both files over to the
+/// reloaded graph, so whatever the simulation did to them is not undone.
+pub fn keep_unchanged_nodes(
+    current: &SceneFile,
+    reloaded: &SceneFile,
+    live: &SceneGraph,
+    graph: &mut SceneGraph,
+) {
+    for node in &reloaded.nodes {
+        if !current.nodes.contains(node) {
+            continue;
+        }
+        if let (Some(live_id), Some(id)) = (live.find(&node.name), graph.find(&node.name)) {
+            *graph.local_mut(id) = *live.node(live_id).local();
+        }
+    }
+    // Nothing is blended from the transforms in the file
+    graph.begin_step();
+}
+
+/// Watches the file a scene was loaded from and applies its changes to the running scene.
+pub struct SceneWatcher {
+    path: PathBuf,
+    // Where the scene's assets are read from, and the file's directory in it
+    vfs: Arc<Vfs>,
+    dir: String,
+    // Contents as last applied
+    file: SceneFile,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+impl SceneWatcher {
+    /// Loads the scene at `path`, with its assets in `vfs`, and starts watching it.
+    pub fn load(path: &Path, aspect: f32, vfs: Arc<Vfs>) -> Result<(Scene, Self)> {
+        let modified = modified(path);
+        let file = scene_file::read(path)?;
+        let dir = vfs.locate(scene_file::directory(path));
+        let scene = file.build(&vfs, &dir, aspect)?;
+        let watcher = Self {
+            path: path.to_path_buf(),
+            vfs,
+            dir,
+            file,
+            modified,
+            checked: Instant::now(),
+        };
+        Ok((scene, watcher))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Applies the scene file to the scene and renderer when it has changed. Returns
+    /// whether it did. On errors the scene is left as it was, and the file is tried
+    /// again once it changes once more.
+    pub fn reload(
+        &mut self,
+        now: Instant,
+        scene: &mut Scene,
+        renderer: &mut Renderer,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<bool> {
+        if now.duration_since(self.checked) < POLL_INTERVAL {
+            return Ok(false);
+        }
+        self.checked = now;
+
+        // Missing while an editor replaces it, the next poll sees the new file
+        let Some(modified) = modified(&self.path) else {
+            return Ok(false);
+        };
+        if self.modified == Some(modified) {
+            return Ok(false);
+        }
+        self.modified = Some(modified);
+
+        let file = scene_file::read(&self.path)?;
+        self.apply(&file, scene, renderer, device, queue)?;
+        self.file = file;
+        Ok(true)
+    }
+
+    fn apply(
+        &self,
+        file: &SceneFile,
+        scene: &mut Scene,
+        renderer: &mut Renderer,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<()> {
+        // Everything that can fail happens before the running scene is touched
+        let mut reloaded = file.build(&self.vfs, &self.dir, 1.0)?;
+        let skybox = if self.file.skybox != file.skybox {
+            Some(renderer.load_skybox(device, queue, &reloaded.skybox, &self.vfs)?)
+        } else {
+            None
+        };
+        let updates = plan_materials(&self.file.materials, &file.materials);
+        renderer.update_materials(device, queue, &reloaded.materials, &updates);
+
+        scene.materials = std::mem::take(&mut reloaded.materials);
+        keep_unchanged_nodes(&self.file, file, &scene.graph, &mut reloaded.graph);
+        scene.graph = reloaded.graph;
+        let instances = scene
+            .graph
+            .nodes()
+            .filter(|(_, node)| node.mesh.is_some())
+            .count();
+        renderer.reserve_instances(device, instances);
+
+        // Left alone unless the file moved it, so flying around survives a reload
+        if self.file.camera != file.camera {
+            let (position, yaw, pitch) = reloaded.camera.pose();
+            scene.camera.set_pose(position, yaw, pitch);
+        }
+        scene.clear_color = reloaded.clear_color;
+        scene.normal_mapping = reloaded.normal_mapping;
+        if let Some(texture) = skybox {
+            renderer.set_skybox(device, texture);
+            scene.skybox = reloaded.skybox;
+        }
+        Ok(())
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::MaterialParams;
+
+    fn material(name: &str, base_color: Option<&str>, roughness: f32) -> MaterialFile {
+        MaterialFile {
+            name: name.to_string(),
+            base_color: base_color.map(str::to_string),
+            metallic_roughness: None,
+            normal: None,
+            occlusion: None,
+            emissive: None,
+            params: MaterialParams {
+                roughness_factor: roughness,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn build(text: &str) -> (SceneFile, Scene) {
+        let file = SceneFile::parse(text, scene_file::Format::Ron).unwrap();
+        let scene = file.build(&Arc::default(), "", 1.0).unwrap();
+        (file, scene)
+    }
+
+    #[test]
+    fn materials_are_matched_by_name() {
+        let current = [
+            material("kept", None, 0.5),
+            material("tweaked", Some("a.png"), 0.5),
+            material("retextured", Some("a.png"), 0.5),
+            material("removed", None, 0.5),
+        ];
+        let reloaded = [
+            material("added", None, 0.5),
+            material("retextured", Some("b.png"), 0.5),
+            material("tweaked", Some("a.png"), 0.9),
+            material("kept", None, 0.5),
+        ];
+        assert_eq!(
+            plan_materials(&current, &reloaded),
+            vec![
+                MaterialUpdate::Upload,
+                MaterialUpdate::Upload,
+                MaterialUpdate::Params(1),
+                MaterialUpdate::Keep(0),
+            ]
+        );
+    }
+
+    #[test]
+    fn unchanged_nodes_keep_their_live_transform() {
+        let (current, mut live) =
+            build(r#"(nodes: [(name: "moving"), (name: "edited", translation: (1.0, 0.0, 0.0))])"#);
+        let moving = live.graph.find("moving").unwrap();
+        live.graph.local_mut(moving).translation.x = 5.0;
+
+        let (reloaded, mut scene) = build(
+            r#"(nodes: [(name: "moving"), (name: "edited", translation: (2.0, 0.0, 0.0)), (name: "added")])"#,
+        );
+        keep_unchanged_nodes(&current, &reloaded, &live.graph, &mut scene.graph);
+
+        let x = |name| {
+            scene
+                .graph
+                .node(scene.graph.find(name).unwrap())
+                .local()
+                .translation
+                .x
+        };
+        assert_eq!(x("moving"), 5.0);
+        assert_eq!(x("edited"), 2.0);
+        assert_eq!(x("added"), 0.0);
+    }
+}
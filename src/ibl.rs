@@ -0,0 +1,411 @@
+use anyhow::Result;
+
+use crate::texture::Texture;
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const WORKGROUP_SIZE: u32 = 8;
+
+pub const ENVIRONMENT_SIZE: u32 = 512;
+pub const IRRADIANCE_SIZE: u32 = 32;
+pub const PREFILTERED_SIZE: u32 = 128;
+// Mip 0 is a mirror, the last mip is fully rough
+pub const PREFILTERED_MIP_COUNT: u32 = 5;
+pub const BRDF_LUT_SIZE: u32 = 256;
+
+const PREFILTER_SAMPLES: u32 = 256;
+const BRDF_LUT_SAMPLES: u32 = 512;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct FilterParams {
+    roughness: f32,
+    sample_count: u32,
+    source_size: f32,
+    _padding: f32,
+}
+
+/// Image based lighting computed from an equirectangular HDR image: the environment
+/// as a cube map, its diffuse irradiance, a prefiltered specular mip chain and the BRDF lookup table.
+pub struct Environment {
+    pub cube: Texture,
+    #[allow(unused)]
+    irradiance: wgpu::Texture,
+    pub irradiance_view: wgpu::TextureView,
+    #[allow(unused)]
+    prefiltered: wgpu::Texture,
+    pub prefiltered_view: wgpu::TextureView,
+    #[allow(unused)]
+    brdf_lut: wgpu::Texture,
+    pub brdf_lut_view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+}
+
+impl Environment {
+    pub fn from_hdr_bytes(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+    ) -> Result<Self> {
+        let img = image::load_from_memory_with_format(bytes, image::ImageFormat::Hdr)?;
+        Ok(Self::from_equirect(device, queue, &img))
+    }
+
+    pub fn from_equirect(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+    ) -> Self {
+        let cube =
+            Texture::cube_from_equirect(device, queue, img, ENVIRONMENT_SIZE, "environment_cube");
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("environment_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let irradiance = create_cube(device, "irradiance_cube", IRRADIANCE_SIZE, 1);
+        let prefiltered = create_cube(
+            device,
+            "prefiltered_cube",
+            PREFILTERED_SIZE,
+            PREFILTERED_MIP_COUNT,
+        );
+        let brdf_lut = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("brdf_lut"),
+            size: wgpu::Extent3d {
+                width: BRDF_LUT_SIZE,
+                height: BRDF_LUT_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let irradiance_view = cube_view_of(&irradiance);
+        let prefiltered_view = cube_view_of(&prefiltered);
+        let brdf_lut_view = brdf_lut.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let filter = Filter::new(device);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("ibl_encoder"),
+        });
+
+        let params = filter.params(FilterParams {
+            roughness: 0.0,
+            sample_count: 0,
+            source_size: ENVIRONMENT_SIZE as f32,
+            _padding: 0.0,
+        });
+        filter.dispatch(
+            &mut encoder,
+            "irradiance",
+            &[
+                (1, wgpu::BindingResource::Sampler(&sampler)),
+                (2, wgpu::BindingResource::TextureView(&cube.view)),
+                (
+                    3,
+                    wgpu::BindingResource::TextureView(&storage_view(&irradiance, 0)),
+                ),
+                (5, params.as_entire_binding()),
+            ],
+            IRRADIANCE_SIZE,
+            6,
+        );
+
+        for mip in 0..PREFILTERED_MIP_COUNT {
+            let params = filter.params(FilterParams {
+                roughness: mip as f32 / (PREFILTERED_MIP_COUNT - 1) as f32,
+                sample_count: PREFILTER_SAMPLES,
+                source_size: ENVIRONMENT_SIZE as f32,
+                _padding: 0.0,
+            });
+            filter.dispatch(
+                &mut encoder,
+                "prefilter",
+                &[
+                    (1, wgpu::BindingResource::Sampler(&sampler)),
+                    (2, wgpu::BindingResource::TextureView(&cube.view)),
+                    (
+                        3,
+                        wgpu::BindingResource::TextureView(&storage_view(&prefiltered, mip)),
+                    ),
+                    (5, params.as_entire_binding()),
+                ],
+                PREFILTERED_SIZE >> mip,
+                6,
+            );
+        }
+
+        let params = filter.params(FilterParams {
+            roughness: 0.0,
+            sample_count: BRDF_LUT_SAMPLES,
+            source_size: 0.0,
+            _padding: 0.0,
+        });
+        filter.dispatch(
+            &mut encoder,
+            "brdf_lut",
+            &[
+                (4, wgpu::BindingResource::TextureView(&brdf_lut_view)),
+                (5, params.as_entire_binding()),
+            ],
+            BRDF_LUT_SIZE,
+            1,
+        );
+
+        queue.submit(std::iter::once(encoder.finish()));
+
+        Self {
+            cube,
+            irradiance,
+            irradiance_view,
+            prefiltered,
+            prefiltered_view,
+            brdf_lut,
+            brdf_lut_view,
+            sampler,
+        }
+    }
+}
+
+/// Projects an equirectangular image onto the six faces of a cube with a full mip chain.
+pub fn equirect_to_cube(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    img: &image::DynamicImage,
+    size: u32,
+    label: &str,
+) -> wgpu::Texture {
+    let equirect = upload_equirect(device, queue, img);
+    let equirect_view = equirect.create_view(&wgpu::TextureViewDescriptor::default());
+
+    // Longitude wraps around the equirectangular image
+    let equirect_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("equirect_sampler"),
+        address_mode_u: wgpu::AddressMode::Repeat,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+
+    let mip_level_count = size.ilog2() + 1;
+    let cube = create_cube(device, label, size, mip_level_count);
+
+    let filter = Filter::new(device);
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("equirect_to_cube_encoder"),
+    });
+
+    // Every mip is projected from the matching mip of the equirectangular image. Filtering
+    // the cube itself would sample and write one texture, which GL cannot do
+    for mip in 0..mip_level_count {
+        filter.dispatch(
+            &mut encoder,
+            "equirect_to_cube",
+            &[
+                (0, wgpu::BindingResource::TextureView(&equirect_view)),
+                (1, wgpu::BindingResource::Sampler(&equirect_sampler)),
+                (
+                    3,
+                    wgpu::BindingResource::TextureView(&storage_view(&cube, mip)),
+                ),
+            ],
+            size >> mip,
+            6,
+        );
+    }
+
+    queue.submit(std::iter::once(encoder.finish()));
+
+    cube
+}
+
+// Compute pipelines sharing the IBL shader module, each with its automatic bind group layout
+struct Filter<'a> {
+    device: &'a wgpu::Device,
+    shader_module: wgpu::ShaderModule,
+}
+
+impl<'a> Filter<'a> {
+    fn new(device: &'a wgpu::Device) -> Self {
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("ibl_shader_module"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shader_ibl.wgsl").into()),
+        });
+
+        Self {
+            device,
+            shader_module,
+        }
+    }
+
+    fn params(&self, params: FilterParams) -> wgpu::Buffer {
+        use wgpu::util::DeviceExt;
+
+        self.device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("ibl_filter_params"),
+                contents: bytemuck::cast_slice(&[params]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            })
+    }
+
+    fn dispatch(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        entry_point: &str,
+        resources: &[(u32, wgpu::BindingResource)],
+        size: u32,
+        layers: u32,
+    ) {
+        let pipeline = self
+            .device
+            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: None,
+                module: &self.shader_module,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            });
+
+        let entries = resources
+            .iter()
+            .map(|(binding, resource)| wgpu::BindGroupEntry {
+                binding: *binding,
+                resource: resource.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(entry_point),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &entries,
+        });
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(entry_point),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        let groups = size.max(1).div_ceil(WORKGROUP_SIZE);
+        compute_pass.dispatch_workgroups(groups, groups, layers);
+    }
+}
+
+// Equirectangular source as half floats so it can be filtered without extra device features,
+// with mips so the small cube mips are not aliased
+fn upload_equirect(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    img: &image::DynamicImage,
+) -> wgpu::Texture {
+    let mut rgba = img.to_rgba32f();
+
+    let size = wgpu::Extent3d {
+        width: rgba.width(),
+        height: rgba.height(),
+        depth_or_array_layers: 1,
+    };
+    let mip_level_count = size.max_mips(wgpu::TextureDimension::D2);
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("environment_equirect"),
+        size,
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+
+    for mip in 0..mip_level_count {
+        if mip > 0 {
+            rgba = image::imageops::resize(
+                &rgba,
+                (rgba.width() / 2).max(1),
+                (rgba.height() / 2).max(1),
+                image::imageops::FilterType::Triangle,
+            );
+        }
+        let pixels = rgba
+            .as_raw()
+            .iter()
+            .map(|&c| half::f16::from_f32(c))
+            .collect::<Vec<_>>();
+
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                aspect: wgpu::TextureAspect::All,
+                texture: &texture,
+                mip_level: mip,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            bytemuck::cast_slice(&pixels),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(8 * rgba.width()),
+                rows_per_image: Some(rgba.height()),
+            },
+            wgpu::Extent3d {
+                width: rgba.width(),
+                height: rgba.height(),
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    texture
+}
+
+fn create_cube(
+    device: &wgpu::Device,
+    label: &str,
+    size: u32,
+    mip_level_count: u32,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 6,
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
+
+fn cube_view_of(texture: &wgpu::Texture) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::Cube),
+        ..Default::default()
+    })
+}
+
+// Six faces of a single mip, for the filters to write to
+fn storage_view(texture: &wgpu::Texture, mip: u32) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        base_mip_level: mip,
+        mip_level_count: Some(1),
+        ..Default::default()
+    })
+}
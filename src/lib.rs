@@ -1,16 +1,50 @@
 mod app;
+mod assets;
+mod bc;
 mod camera;
+mod config;
+mod error;
+mod frame;
+mod gpu;
+mod gpu_timer;
+mod graph;
+mod hot_reload;
+mod ibl;
+mod light;
+mod loader;
+mod material;
+mod material_table;
+mod mesh;
+mod overlay;
+pub mod pack;
+mod profiler;
+mod renderer;
+mod scene;
+mod scene_file;
+mod shadow;
+mod skybox;
+mod stats;
 mod texture;
-
-use camera::Camera;
-
-use cgmath::Zero;
-use pollster::FutureExt;
+mod texture_data;
+mod vfs;
+
+use config::{GpuConfig, Options};
+pub use error::RendererError;
+use gpu::Gpu;
+use hot_reload::SceneWatcher;
+use profiler::Profiler;
+use renderer::Renderer;
+use scene::{MeshInstance, Scene};
+pub use stats::{FrameStats, FrameTiming, PassTiming, StatsSummary, Summary};
+use vfs::Vfs;
+
+use cgmath::{Matrix, Rotation3, SquareMatrix};
+use std::ops::Range;
 use std::sync::Arc;
-use wgpu::util::{DeviceExt, RenderEncoder};
-use winit::dpi::PhysicalSize;
+use std::time::{Duration, Instant};
+use winit::keyboard::{KeyCode, PhysicalKey};
 use winit::{
-    event::WindowEvent,
+    event::{ElementState, KeyEvent, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::Window,
 };
@@ -20,6 +54,9 @@ use winit::{
 struct Vertex {
     position: [f32; 3],
     tex_coords: [f32; 2],
+    normal: [f32; 3],
+    // xyz along increasing u, w is the handedness of the bitangent
+    tangent: [f32; 4],
 }
 impl Vertex {
     fn desc() -> wgpu::VertexBufferLayout<'static> {
@@ -37,30 +74,46 @@ impl Vertex {
                     shader_location: 1,
                     format: wgpu::VertexFormat::Float32x2,
                 },
+                wgpu::VertexAttribute {
+                    offset: size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
 }
 
-struct Instance {
-    position: cgmath::Vector3<f32>,
-    rotation: cgmath::Quaternion<f32>,
-}
-impl Instance {
-    fn to_raw(&self) -> InstanceRaw {
-        InstanceRaw {
-            model: (cgmath::Matrix4::from_translation(self.position)
-                * cgmath::Matrix4::from(self.rotation))
-            .into(),
-        }
-    }
-}
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct InstanceRaw {
     model: [[f32; 4]; 4],
+    normal: [[f32; 3]; 3],
+    // Row of the material table the instance is drawn with
+    material: u32,
 }
 impl InstanceRaw {
+    fn new(model: cgmath::Matrix4<f32>, material: usize) -> Self {
+        // Normals need the inverse transpose so non-uniform scales keep them perpendicular
+        let linear =
+            cgmath::Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+        let normal = linear
+            .invert()
+            .unwrap_or(cgmath::Matrix3::identity())
+            .transpose();
+
+        Self {
+            model: model.into(),
+            normal: normal.into(),
+            material: material as u32,
+        }
+    }
+
     fn desc() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
         wgpu::VertexBufferLayout {
@@ -87,6 +140,26 @@ impl InstanceRaw {
                     shader_location: 8,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 25]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Uint32,
+                },
             ],
         }
     }
@@ -97,453 +170,595 @@ const VERTICES: &[Vertex] = &[
     Vertex {
         position: [-0.5, -0.5, 0.5],
         tex_coords: [0.0, 1.0],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [0.0; 4],
     }, // 0
     Vertex {
         position: [0.5, -0.5, 0.5],
         tex_coords: [1.0, 1.0],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [0.0; 4],
     }, // 1
     Vertex {
         position: [0.5, 0.5, 0.5],
         tex_coords: [1.0, 0.0],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [0.0; 4],
     }, // 2
     Vertex {
         position: [-0.5, 0.5, 0.5],
         tex_coords: [0.0, 0.0],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [0.0; 4],
     }, // 3
-    // Back face
+    // Right face
     Vertex {
-        position: [-0.5, -0.5, -0.5],
-        tex_coords: [1.0, 1.0],
+        position: [0.5, -0.5, 0.5],
+        tex_coords: [0.0, 1.0],
+        normal: [1.0, 0.0, 0.0],
+        tangent: [0.0; 4],
     }, // 4
     Vertex {
         position: [0.5, -0.5, -0.5],
-        tex_coords: [0.0, 1.0],
+        tex_coords: [1.0, 1.0],
+        normal: [1.0, 0.0, 0.0],
+        tangent: [0.0; 4],
     }, // 5
     Vertex {
         position: [0.5, 0.5, -0.5],
-        tex_coords: [0.0, 0.0],
+        tex_coords: [1.0, 0.0],
+        normal: [1.0, 0.0, 0.0],
+        tangent: [0.0; 4],
     }, // 6
+    Vertex {
+        position: [0.5, 0.5, 0.5],
+        tex_coords: [0.0, 0.0],
+        normal: [1.0, 0.0, 0.0],
+        tangent: [0.0; 4],
+    }, // 7
+    // Back face
+    Vertex {
+        position: [0.5, -0.5, -0.5],
+        tex_coords: [0.0, 1.0],
+        normal: [0.0, 0.0, -1.0],
+        tangent: [0.0; 4],
+    }, // 8
+    Vertex {
+        position: [-0.5, -0.5, -0.5],
+        tex_coords: [1.0, 1.0],
+        normal: [0.0, 0.0, -1.0],
+        tangent: [0.0; 4],
+    }, // 9
     Vertex {
         position: [-0.5, 0.5, -0.5],
         tex_coords: [1.0, 0.0],
-    }, // 7
+        normal: [0.0, 0.0, -1.0],
+        tangent: [0.0; 4],
+    }, // 10
+    Vertex {
+        position: [0.5, 0.5, -0.5],
+        tex_coords: [0.0, 0.0],
+        normal: [0.0, 0.0, -1.0],
+        tangent: [0.0; 4],
+    }, // 11
+    // Left face
+    Vertex {
+        position: [-0.5, -0.5, -0.5],
+        tex_coords: [0.0, 1.0],
+        normal: [-1.0, 0.0, 0.0],
+        tangent: [0.0; 4],
+    }, // 12
+    Vertex {
+        position: [-0.5, -0.5, 0.5],
+        tex_coords: [1.0, 1.0],
+        normal: [-1.0, 0.0, 0.0],
+        tangent: [0.0; 4],
+    }, // 13
+    Vertex {
+        position: [-0.5, 0.5, 0.5],
+        tex_coords: [1.0, 0.0],
+        normal: [-1.0, 0.0, 0.0],
+        tangent: [0.0; 4],
+    }, // 14
+    Vertex {
+        position: [-0.5, 0.5, -0.5],
+        tex_coords: [0.0, 0.0],
+        normal: [-1.0, 0.0, 0.0],
+        tangent: [0.0; 4],
+    }, // 15
+    // Top face
+    Vertex {
+        position: [-0.5, 0.5, 0.5],
+        tex_coords: [0.0, 1.0],
+        normal: [0.0, 1.0, 0.0],
+        tangent: [0.0; 4],
+    }, // 16
+    Vertex {
+        position: [0.5, 0.5, 0.5],
+        tex_coords: [1.0, 1.0],
+        normal: [0.0, 1.0, 0.0],
+        tangent: [0.0; 4],
+    }, // 17
+    Vertex {
+        position: [0.5, 0.5, -0.5],
+        tex_coords: [1.0, 0.0],
+        normal: [0.0, 1.0, 0.0],
+        tangent: [0.0; 4],
+    }, // 18
+    Vertex {
+        position: [-0.5, 0.5, -0.5],
+        tex_coords: [0.0, 0.0],
+        normal: [0.0, 1.0, 0.0],
+        tangent: [0.0; 4],
+    }, // 19
+    // Bottom face
+    Vertex {
+        position: [-0.5, -0.5, -0.5],
+        tex_coords: [0.0, 1.0],
+        normal: [0.0, -1.0, 0.0],
+        tangent: [0.0; 4],
+    }, // 20
+    Vertex {
+        position: [0.5, -0.5, -0.5],
+        tex_coords: [1.0, 1.0],
+        normal: [0.0, -1.0, 0.0],
+        tangent: [0.0; 4],
+    }, // 21
+    Vertex {
+        position: [0.5, -0.5, 0.5],
+        tex_coords: [1.0, 0.0],
+        normal: [0.0, -1.0, 0.0],
+        tangent: [0.0; 4],
+    }, // 22
+    Vertex {
+        position: [-0.5, -0.5, 0.5],
+        tex_coords: [0.0, 0.0],
+        normal: [0.0, -1.0, 0.0],
+        tangent: [0.0; 4],
+    }, // 23
 ];
 
 const INDICES: &[u16] = &[
-    // Front face
-    0, 1, 2, 2, 3, 0, // Right face
-    1, 5, 6, 6, 2, 1, // Back face
-    5, 4, 7, 7, 6, 5, // Left face
-    4, 0, 3, 3, 7, 4, // Top face
-    3, 2, 6, 6, 7, 3, // Bottom face
-    4, 5, 1, 1, 0, 4,
+    0, 1, 2, 2, 3, 0, // Front face
+    4, 5, 6, 6, 7, 4, // Right face
+    8, 9, 10, 10, 11, 8, // Back face
+    12, 13, 14, 14, 15, 12, // Left face
+    16, 17, 18, 18, 19, 16, // Top face
+    20, 21, 22, 22, 23, 20, // Bottom face
 ];
 
+/// Splits the instance list into consecutive runs that use the same material.
+fn material_batches(instances: &[MeshInstance]) -> impl Iterator<Item = (usize, Range<u32>)> + '_ {
+    let mut start = 0;
+    std::iter::from_fn(move || {
+        let material = instances.get(start)?.material;
+        let len = instances[start..]
+            .iter()
+            .take_while(|instance| instance.material == material)
+            .count();
+        let range = start as u32..(start + len) as u32;
+        start += len;
+        Some((material, range))
+    })
+}
+
 struct State {
-    surface: wgpu::Surface<'static>,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
-    config: wgpu::SurfaceConfiguration,
+    gpu_config: GpuConfig,
     size: winit::dpi::PhysicalSize<u32>,
     window: Arc<Window>,
-    render_pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
-    num_indices: u32,
-    instances: Vec<Instance>,
-    instance_buffer: wgpu::Buffer,
-    diffuse_bind_group: wgpu::BindGroup,
-    diffuse_texture: texture::Texture,
-    camera: Camera,
-    camera_buffer: wgpu::Buffer,
-    camera_bind_group: wgpu::BindGroup,
-    // challenge 1
-    clear_color: wgpu::Color,
+    gpu: Gpu,
+    renderer: Renderer,
+    scene: Scene,
+    // Only for scenes loaded from a file
+    watcher: Option<SceneWatcher>,
+    stats: FrameStats,
+    // Toggled with F3
+    show_stats: bool,
+    title_updated: Instant,
+    profiler: Profiler,
 }
 
+// Frames captured into a trace when F5 is pressed
+const TRACE_FRAMES: u32 = 300;
+const TRACE_PATH: &str = "trace.json";
+// Written when F6 is pressed
+const SCENE_PATH: &str = "scene.ron";
+
 impl State {
-    pub fn new(window: Window) -> Self {
+    /// Shows `scene` in the window, or the demo scene when there is none. The scene is
+    /// kept in sync with its file when a `watcher` is given.
+    pub fn new(
+        window: Window,
+        scene: Option<Scene>,
+        watcher: Option<SceneWatcher>,
+        gpu_config: GpuConfig,
+    ) -> Result<Self, RendererError> {
         let window_arc = Arc::new(window);
         let size = window_arc.inner_size();
-        let instance = Self::create_gpu_instance();
-        let surface = instance
-            .create_surface(window_arc.clone())
-            .expect("Could not create surface");
-        let adapter = Self::create_adapter(instance, &surface);
-        let (device, queue) = Self::create_device(&adapter);
-        let surface_caps = surface.get_capabilities(&adapter);
-        let config = Self::create_surface_config(surface_caps, size);
-
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("vertex_buffer"),
-            contents: bytemuck::cast_slice(VERTICES),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-
-        let num_indices = INDICES.len() as u32;
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("index_buffer"),
-            contents: bytemuck::cast_slice(INDICES),
-            usage: wgpu::BufferUsages::INDEX,
-        });
-
-        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("shader_module"),
-            source: wgpu::ShaderSource::Wgsl(
-                include_str!("shader_texture_camera_instanced.wgsl").into(),
-            ),
-        });
-
-        surface.configure(&device, &config);
-
-        let diffuse_bytes = include_bytes!("../assets/happy-tree.png");
-        let diffuse_texture =
-            texture::Texture::from_bytes(&device, &queue, diffuse_bytes, "happy-tree.png").unwrap();
-
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("texture_bind_group_layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-            });
-
-        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("diffuse_bind_group"),
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-        });
-
-        let instances = vec![
-            Instance {
-                position: cgmath::Vector3 {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                rotation: cgmath::Quaternion {
-                    v: cgmath::Vector3::zero(),
-                    s: 0.0,
-                },
-            },
-            Instance {
-                position: cgmath::Vector3 {
-                    x: 1.0,
-                    y: 1.0,
-                    z: 1.0,
-                },
-                rotation: cgmath::Quaternion {
-                    v: cgmath::Vector3::zero(),
-                    s: 0.0,
-                },
-            },
-        ];
-
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Instance Buffer"),
-            contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let aspect = (config.width / config.height) as f32;
-        let camera = Camera::default(aspect);
-
-        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("camera_buffer"),
-            contents: bytemuck::cast_slice(&[camera.view_proj()]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let camera_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("camera_bind_group_layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-            });
-
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("camera_bind_group"),
-            layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
-        });
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("render_pipeline_layout"),
-                bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-
-        let render_pipeline =
-            Self::create_render_pipeline(&render_pipeline_layout, &device, &config, &shader_module);
-
-        let clear_color = wgpu::Color {
-            r: 0.3,
-            g: 0.3,
-            b: 0.2,
-            a: 1.0,
+        let aspect = gpu::aspect_ratio(size);
+        let scene = match scene {
+            Some(mut scene) => {
+                scene.camera.update_aspect(aspect);
+                scene
+            }
+            None => Scene::new(aspect),
         };
-
-        Self {
-            surface,
-            device,
-            queue,
-            config,
-            size,
-            window: window_arc,
-            render_pipeline,
-            vertex_buffer,
-            index_buffer,
-            num_indices,
-            instances,
-            instance_buffer,
-            diffuse_bind_group,
-            diffuse_texture,
-            camera,
-            camera_buffer,
-            camera_bind_group,
-            // Challenge 1
-            clear_color,
-        }
+        Self::with_scene(window_arc, scene, watcher, gpu_config)
     }
 
-    fn create_gpu_instance() -> wgpu::Instance {
-        wgpu::Instance::new(&wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::PRIMARY,
-            ..Default::default()
+    fn with_scene(
+        window: Arc<Window>,
+        scene: Scene,
+        watcher: Option<SceneWatcher>,
+        gpu_config: GpuConfig,
+    ) -> Result<Self, RendererError> {
+        let size = window.inner_size();
+        let gpu = Gpu::new(window.clone(), &gpu_config)?;
+        let renderer = Renderer::new(&gpu.device, &gpu.queue, &gpu.config, &scene)?;
+
+        Ok(Self {
+            gpu_config,
+            size,
+            window,
+            gpu,
+            renderer,
+            scene,
+            watcher,
+            stats: FrameStats::default(),
+            show_stats: false,
+            title_updated: Instant::now(),
+            profiler: Profiler::default(),
         })
     }
 
-    fn create_adapter(instance: wgpu::Instance, surface: &wgpu::Surface) -> wgpu::Adapter {
-        instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .block_on()
-            .expect("Failed to find adapter")
+    pub fn is_device_lost(&self) -> bool {
+        self.gpu.is_lost()
     }
 
-    fn create_device(adapter: &wgpu::Adapter) -> (wgpu::Device, wgpu::Queue) {
-        adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    required_features: wgpu::Features::empty(),
-                    // WebGL doesn't support all of wgpu's features, so if
-                    // we're building for the web, we'll have to disable some,
-                    required_limits: wgpu::Limits::default(),
-                    label: None,
-                    memory_hints: Default::default(),
-                },
-                None, // Trace path
-            )
-            .block_on()
-            .expect("Failed to create device")
-    }
-
-    fn create_surface_config(
-        surface_caps: wgpu::SurfaceCapabilities,
-        size: PhysicalSize<u32>,
-    ) -> wgpu::SurfaceConfiguration {
-        // find surface format with sRGB
-        let surface_format = surface_caps
-            .formats
-            .iter()
-            .find(|f| f.is_srgb())
-            .copied()
-            .unwrap_or(surface_caps.formats[0]);
-        wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: size.width,
-            height: size.height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
-            view_formats: vec![],
-            desired_maximum_frame_latency: 2,
-        }
-    }
-
-    fn create_render_pipeline(
-        layout: &wgpu::PipelineLayout,
-        device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
-        shader_module: &wgpu::ShaderModule,
-    ) -> wgpu::RenderPipeline {
-        // Render pipeline object to be returned
-        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("render_pipeline"),
-            layout: Some(layout),
-            vertex: wgpu::VertexState {
-                module: shader_module,
-                entry_point: Some("vs_main"),
-                buffers: &[Vertex::desc(), InstanceRaw::desc()],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: shader_module,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Other modes besides Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-            cache: None,
-        })
+    /// Replaces a lost device. The adapter, device, surface and every GPU resource
+    /// are created again from the scene, which lives on the CPU and survives the loss.
+    pub fn recover_device(self) -> Result<Self, RendererError> {
+        log::info!("Recreating the GPU device and all resources");
+        let Self {
+            gpu_config,
+            window,
+            scene,
+            watcher,
+            gpu,
+            renderer,
+            ..
+        } = self;
+        // Release the old surface before a new one is created for the same window
+        drop(renderer);
+        drop(gpu);
+        Self::with_scene(window, scene, watcher, gpu_config)
     }
 
     pub fn window(&self) -> &Window {
         &self.window
     }
 
+    /// True while the window has no area. Nothing is configured or rendered then.
+    pub fn is_minimized(&self) -> bool {
+        self.size.width == 0 || self.size.height == 0
+    }
+
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         self.size = new_size;
-        self.config.width = new_size.width;
-        self.config.height = new_size.height;
-        self.surface.configure(&self.device, &self.config);
+        if !self.gpu.resize(new_size) {
+            return;
+        }
+        self.renderer.resize(&self.gpu.device, &self.gpu.config);
 
-        let aspect = new_size.width as f32 / new_size.height as f32;
-        self.camera.update_aspect(aspect);
+        // The surface may be clamped smaller, but it is still stretched over the whole window
+        self.scene.camera.update_aspect(gpu::aspect_ratio(new_size));
     }
 
     fn input(&mut self, event: &WindowEvent) -> bool {
-        self.camera.process_events(event)
+        if let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(code),
+                    repeat: false,
+                    ..
+                },
+            ..
+        } = event
+        {
+            match code {
+                KeyCode::KeyN => {
+                    self.scene.normal_mapping = !self.scene.normal_mapping;
+                    log::info!("Normal mapping: {}", self.scene.normal_mapping);
+                    return true;
+                }
+                KeyCode::KeyV => {
+                    self.toggle_vsync();
+                    return true;
+                }
+                KeyCode::KeyC => {
+                    self.toggle_camera_attachment();
+                    return true;
+                }
+                KeyCode::F3 => {
+                    self.show_stats = !self.show_stats;
+                    return true;
+                }
+                KeyCode::F4 => {
+                    self.export_stats("frame_stats.csv");
+                    return true;
+                }
+                KeyCode::F5 => {
+                    self.start_trace(TRACE_FRAMES);
+                    return true;
+                }
+                KeyCode::F6 => {
+                    self.save_scene(SCENE_PATH);
+                    return true;
+                }
+                _ => (),
+            }
+        }
+
+        self.scene.camera.process_events(event)
     }
 
-    pub fn update(&mut self) {
-        self.camera.update_camera();
-        self.queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera.view_proj()]),
+    fn toggle_vsync(&mut self) {
+        let vsync = !matches!(
+            self.gpu.config.present_mode,
+            wgpu::PresentMode::Immediate | wgpu::PresentMode::Mailbox
         );
+        let requested = if vsync {
+            wgpu::PresentMode::AutoNoVsync
+        } else {
+            wgpu::PresentMode::AutoVsync
+        };
+        // Kept in the config so a recreated device presents the same way
+        self.gpu_config.present_mode = requested;
+        let mode = self.gpu.set_present_mode(requested);
+        log::info!("Present mode: {mode:?}");
+        self.resize(self.size);
+    }
 
-        self.instances[0].position.x = self.instances[0].position.x % 10.0 + 0.1;
-        let instance_data = self
-            .instances
-            .iter()
-            .map(Instance::to_raw)
-            .collect::<Vec<_>>();
-        self.queue.write_buffer(
-            &self.instance_buffer,
-            0,
-            &bytemuck::cast_slice(&instance_data),
+    /// Runs `steps` fixed simulation steps, then uploads the scene blended `alpha` of the
+    /// way from the previous step to the current one.
+    pub fn update(&mut self, steps: u32, alpha: f32) {
+        let start = Instant::now();
+        self.stats.begin_frame(start);
+        if let Some(events) = self.profiler.begin_frame(start) {
+            self.export_trace(&events, TRACE_PATH);
+        }
+        let update = self.profiler.scope("update");
+        self.reload_scene(start);
+
+        let scope = self.profiler.scope("stream_assets");
+        self.renderer
+            .stream_assets(&self.gpu.device, &self.gpu.queue);
+        self.profiler.end(scope);
+
+        for _ in 0..steps {
+            let step = self.profiler.scope("fixed_update");
+            self.fixed_update();
+            self.profiler.end(step);
+        }
+
+        self.scene.interpolate(alpha);
+        self.renderer
+            .update(&self.gpu.queue, &self.scene, &self.profiler);
+        self.profiler.end(update);
+        self.stats.record_update(start.elapsed());
+    }
+
+    // Picks up edits to the scene file, a broken file leaves the scene as it is
+    fn reload_scene(&mut self, now: Instant) {
+        let Some(watcher) = self.watcher.as_mut() else {
+            return;
+        };
+        let result = watcher.reload(
+            now,
+            &mut self.scene,
+            &mut self.renderer,
+            &self.gpu.device,
+            &self.gpu.queue,
         );
+        let path = watcher.path().display();
+        match result {
+            Ok(false) => (),
+            Ok(true) => log::info!("Reloaded {path}"),
+            Err(error) => {
+                log::warn!("Could not reload {path}, keeping the scene as it was: {error:#}")
+            }
+        }
+    }
+
+    /// Records the next `frames` frames and writes them as a Chrome trace.
+    pub fn start_trace(&mut self, frames: u32) {
+        log::info!("Tracing {frames} frames");
+        self.profiler.start_capture(frames);
+    }
+
+    fn export_trace(&self, events: &[profiler::TraceEvent], path: &str) {
+        let result = std::fs::File::create(path)
+            .and_then(|file| profiler::write_trace(events, std::io::BufWriter::new(file)));
+        match result {
+            Ok(()) => log::info!("Wrote trace to {path}, open it in about://tracing or Perfetto"),
+            Err(error) => log::error!("Could not write trace to {path}: {error}"),
+        }
+    }
+
+    fn save_scene(&self, path: &str) {
+        match scene_file::save(&self.scene, std::path::Path::new(path)) {
+            Ok(()) => log::info!("Saved the scene to {path}"),
+            Err(error) => log::error!("Could not save the scene to {path}: {error:#}"),
+        }
+    }
+
+    fn export_stats(&self, path: &str) {
+        let result = std::fs::File::create(path)
+            .and_then(|file| self.stats.write_csv(std::io::BufWriter::new(file)));
+        match result {
+            Ok(()) => log::info!("Wrote frame stats to {path}"),
+            Err(error) => log::error!("Could not write frame stats to {path}: {error}"),
+        }
+    }
+
+    // Advances the simulation by one tick, independent of the frame rate
+    fn fixed_update(&mut self) {
+        self.scene.begin_step();
+        self.scene.camera.update_camera();
+
+        let graph = &mut self.scene.graph;
+        if let Some(tree) = graph.find("tree") {
+            let translation = &mut graph.local_mut(tree).translation;
+            let wraps = translation.x >= 10.0;
+            translation.x = translation.x % 10.0 + 0.1;
+            // Jump back to the start rather than sweep across the scene for a tick
+            if wraps {
+                graph.snap(tree);
+            }
+        }
+        if let Some(pivot) = graph.find("gold_pivot") {
+            let rotation = &mut graph.local_mut(pivot).rotation;
+            *rotation = cgmath::Quaternion::from_angle_y(cgmath::Rad(0.02)) * *rotation;
+        }
+
+        // Swing the key light around so its shadows visibly move
+        if let Some(pivot) = graph.find("light_pivot") {
+            let rotation = &mut graph.local_mut(pivot).rotation;
+            *rotation = cgmath::Quaternion::from_angle_y(cgmath::Rad(0.01)) * *rotation;
+        }
+    }
+
+    // Rides along with the tree, or goes back to flying freely
+    fn toggle_camera_attachment(&mut self) {
+        let (Some(rig), Some(tree)) = (self.scene.camera_node(), self.scene.graph.find("tree"))
+        else {
+            return;
+        };
+        let graph = &mut self.scene.graph;
+        let parent = match graph.node(rig).parent() {
+            Some(_) => None,
+            None => Some(tree),
+        };
+        graph.set_parent(rig, parent);
+        log::info!("Camera attached to the tree: {}", parent.is_some());
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
+        let acquire = self.profiler.scope("acquire");
+        let output = self.gpu.surface.get_current_texture()?;
+        self.profiler.end(acquire);
 
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
 
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("render_encoder"),
-            });
+        let start = Instant::now();
+        let render = self.profiler.scope("render");
+        let stats = self.show_stats.then_some(&self.stats);
+        self.renderer.render(
+            &self.gpu.device,
+            &self.gpu.queue,
+            &view,
+            &self.scene,
+            stats,
+            &self.profiler,
+        );
+        self.profiler.end(render);
+        self.stats.record_encode(start.elapsed());
+
+        let present = self.profiler.scope("present");
+        output.present();
+        self.profiler.end(present);
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("render_pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(self.clear_color),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
-
-            render_pass.set_pipeline(&self.render_pipeline);
-
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..self.instances.len() as _);
+        if let Some(frame) = self.renderer.gpu_frame(&self.gpu.device) {
+            self.stats.record_gpu(&frame.timings());
+            self.profiler.record_gpu(&frame);
         }
 
-        // Submit will accept anything that implements IntoIter
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+        if self.title_updated.elapsed() >= Duration::from_secs(1) {
+            self.title_updated = Instant::now();
+            let mut title = format!("Hello WGPU! | {}", self.stats.summary());
+            let progress = self.renderer.asset_progress();
+            if !progress.is_done() {
+                let loaded = progress.total - progress.loading;
+                title += &format!(" | loading textures {loaded}/{}", progress.total);
+            }
+            self.window.set_title(&title);
+        }
 
         Ok(())
     }
 }
 
-pub async fn run() {
+impl frame::SurfaceTarget for State {
+    fn reconfigure(&mut self) {
+        self.resize(self.size);
+    }
+}
+
+pub async fn run() -> Result<(), RendererError> {
     env_logger::init();
-    let event_loop = EventLoop::new().expect("Could not create event loop");
-    event_loop.set_control_flow(ControlFlow::Poll);
-    let mut app = app::App::default();
+    let options = Options::from_args(std::env::args().skip(1))?;
+    if options.help {
+        println!("{}", config::USAGE);
+        return Ok(());
+    }
+    if options.list_adapters {
+        gpu::list_adapters(&options.gpu);
+        return Ok(());
+    }
+
+    let root = options.assets.as_deref();
+    if let Some(root) = root.filter(|root| !root.is_dir()) {
+        return Err(RendererError::Arguments(format!(
+            "asset directory {} does not exist",
+            root.display()
+        )));
+    }
+    let mut pack_paths = options.packs.clone();
+    if pack_paths.is_empty() && std::path::Path::new(vfs::DEFAULT_PACK).is_file() {
+        pack_paths.push(vfs::DEFAULT_PACK.into());
+    }
+    let packs = pack_paths
+        .iter()
+        .map(|path| {
+            pack::Pack::open(path)
+                .map_err(|error| RendererError::asset(&path.to_string_lossy())(error.into()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut vfs = Vfs::standard(
+        root.unwrap_or(std::path::Path::new(vfs::DEFAULT_ROOT)),
+        packs,
+    );
+    vfs.set_cache(
+        options
+            .cache
+            .clone()
+            .unwrap_or_else(|| std::env::temp_dir().join("rust_wgpu_textures")),
+    );
+    let vfs = Arc::new(vfs);
+
+    // Loaded before the window opens so a broken scene fails right away. The camera
+    // aspect is corrected once the window size is known.
+    let (scene, watcher) = match options.scene.as_deref() {
+        Some(path) => {
+            let (scene, watcher) = SceneWatcher::load(path, 1.0, vfs)
+                .map_err(RendererError::asset(&path.to_string_lossy()))?;
+            (scene, Some(watcher))
+        }
+        None => (scene_file::demo(1.0, vfs), None),
+    };
+
+    let event_loop = EventLoop::new()?;
+    event_loop.set_control_flow(ControlFlow::Wait);
+    let mut app = app::App::new(options, Some(scene), watcher);
 
-    event_loop.run_app(&mut app);
+    event_loop.run_app(&mut app)?;
+
+    // Errors during startup stop the event loop and are reported here
+    match app.take_error() {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
 }
@@ -0,0 +1,73 @@
+use bytemuck::Zeroable;
+
+// Must match MAX_POINT_LIGHTS in the lit shader
+pub const MAX_POINT_LIGHTS: usize = 4;
+
+#[derive(Clone)]
+pub struct PointLight {
+    pub position: cgmath::Point3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    // Distance at which the light has faded out completely, also the far plane of its shadow map
+    pub range: f32,
+    pub casts_shadows: bool,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PointLightRaw {
+    position: [f32; 3],
+    range: f32,
+    color: [f32; 3],
+    // Cube index in the shadow map array, -1 when the light casts no shadows
+    shadow_index: i32,
+}
+
+// Data uploaded to the light uniform buffer
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightUniform {
+    lights: [PointLightRaw; MAX_POINT_LIGHTS],
+    // Scale of the image based ambient lighting
+    environment_intensity: f32,
+    count: u32,
+    // Non-zero when materials should sample their normal maps
+    normal_mapping: u32,
+    _padding: u32,
+}
+impl LightUniform {
+    pub fn new(lights: &[PointLight], environment_intensity: f32, normal_mapping: bool) -> Self {
+        let mut raw = [PointLightRaw::zeroed(); MAX_POINT_LIGHTS];
+        let shadow_indices = shadow_indices(lights);
+
+        for ((raw, light), shadow_index) in raw.iter_mut().zip(lights).zip(shadow_indices) {
+            *raw = PointLightRaw {
+                position: light.position.into(),
+                range: light.range,
+                color: light.color.map(|c| c * light.intensity),
+                shadow_index: shadow_index.map_or(-1, |i| i as i32),
+            };
+        }
+
+        Self {
+            lights: raw,
+            environment_intensity,
+            count: lights.len().min(MAX_POINT_LIGHTS) as u32,
+            normal_mapping: normal_mapping as u32,
+            _padding: 0,
+        }
+    }
+}
+
+/// Assigns each shadow casting light a cube in the shadow map array, in order.
+pub fn shadow_indices(lights: &[PointLight]) -> impl Iterator<Item = Option<usize>> + '_ {
+    let mut next = 0;
+    lights.iter().take(MAX_POINT_LIGHTS).map(move |light| {
+        if light.casts_shadows {
+            next += 1;
+            Some(next - 1)
+        } else {
+            None
+        }
+    })
+}
@@ -0,0 +1,259 @@
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use crate::assets::Handle;
+use crate::texture::Texture;
+use crate::texture_data::{TextureData, TextureKind};
+use crate::vfs::Vfs;
+
+// Leaves a core to the main thread, more rarely helps with a handful of textures
+const MAX_WORKERS: usize = 4;
+// Part of every cache file name, raised when the encoders change so old results are not reused
+const CACHE_VERSION: u32 = 1;
+
+struct Job {
+    handle: Handle<Texture>,
+    path: String,
+    kind: TextureKind,
+    vfs: Arc<Vfs>,
+}
+
+/// A texture decoded by a worker, waiting to be uploaded on the main thread.
+pub struct Decoded {
+    pub handle: Handle<Texture>,
+    pub path: String,
+    pub data: anyhow::Result<TextureData>,
+}
+
+impl Decoded {
+    /// Bytes the upload of the texture writes to the GPU.
+    pub fn size(&self) -> usize {
+        self.data.as_ref().map_or(0, TextureData::size)
+    }
+}
+
+/// Pool of threads that read and decode images, so large ones do not stall the frame.
+pub struct Loader {
+    // Dropped first so the workers see the channel close and stop
+    jobs: Option<Sender<Job>>,
+    decoded: Receiver<Decoded>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Loader {
+    /// Workers for all but one core. When `compress` is set they block compress the images
+    /// they decode, for a device with `TEXTURE_COMPRESSION_BC`.
+    pub fn new(compress: bool) -> Self {
+        let count = std::thread::available_parallelism()
+            .map_or(1, |cores| cores.get().saturating_sub(1))
+            .clamp(1, MAX_WORKERS);
+        Self::with_workers(count, compress)
+    }
+
+    pub fn with_workers(count: usize, compress: bool) -> Self {
+        let (jobs, job_receiver) = mpsc::channel::<Job>();
+        let (decoded_sender, decoded) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let workers = (0..count)
+            .map(|index| {
+                let jobs = job_receiver.clone();
+                let decoded = decoded_sender.clone();
+                std::thread::Builder::new()
+                    .name(format!("asset_loader_{index}"))
+                    .spawn(move || work(&jobs, &decoded, compress))
+                    .expect("could not start an asset loader thread")
+            })
+            .collect();
+
+        Self {
+            jobs: Some(jobs),
+            decoded,
+            workers,
+        }
+    }
+
+    /// Queues the file at `path` in `vfs` to be read and decoded for the texture of `handle`.
+    pub fn load(&self, handle: Handle<Texture>, path: &str, kind: TextureKind, vfs: &Arc<Vfs>) {
+        if let Some(jobs) = &self.jobs {
+            let job = Job {
+                handle,
+                path: path.to_string(),
+                kind,
+                vfs: vfs.clone(),
+            };
+            // Only fails once every worker is gone, which they never are while the loader lives
+            let _ = jobs.send(job);
+        }
+    }
+
+    /// Images decoded since the last call, without waiting for more.
+    pub fn finished(&self) -> impl Iterator<Item = Decoded> + '_ {
+        self.decoded.try_iter()
+    }
+}
+
+impl Drop for Loader {
+    fn drop(&mut self) {
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn work(jobs: &Mutex<Receiver<Job>>, decoded: &Sender<Decoded>, compress: bool) {
+    loop {
+        // The lock is released before decoding, so the others can pick up jobs meanwhile
+        let job = jobs.lock().map(|jobs| jobs.recv());
+        let Ok(Ok(Job {
+            handle,
+            path,
+            kind,
+            vfs,
+        })) = job
+        else {
+            return;
+        };
+        let data = vfs
+            .read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| import(&path, &bytes, kind, compress, vfs.cache()));
+        let result = Decoded { handle, path, data };
+        if decoded.send(result).is_err() {
+            return;
+        }
+    }
+}
+
+// Decodes a texture file, block compressing it when asked to. Compressed results are kept in
+// `cache`, named after the file's contents, so later runs skip the encoding.
+fn import(
+    path: &str,
+    bytes: &[u8],
+    kind: TextureKind,
+    compress: bool,
+    cache: Option<&Path>,
+) -> anyhow::Result<TextureData> {
+    if !compress {
+        return TextureData::decode(bytes, kind.is_srgb());
+    }
+    let name = format!(
+        "{:08x}-{:x}-{kind:?}-v{CACHE_VERSION}.dds",
+        crc32fast::hash(bytes),
+        bytes.len()
+    );
+    let cached = cache.map(|dir| dir.join(name.to_lowercase()));
+    if let Some(data) = cached
+        .as_ref()
+        .and_then(|file| std::fs::read(file).ok())
+        .and_then(|cached| TextureData::decode(&cached, kind.is_srgb()).ok())
+    {
+        return Ok(data);
+    }
+
+    let data = TextureData::decode(bytes, kind.is_srgb())?;
+    let format = data.format;
+    let data = data.compressed(kind);
+    if let Some(file) = cached.filter(|_| data.format != format) {
+        // Written aside and renamed, so a run that stops halfway leaves no broken file behind
+        let partial = file.with_extension("partial");
+        let written = std::fs::create_dir_all(file.parent().unwrap())
+            .map_err(anyhow::Error::from)
+            .and_then(|_| Ok(std::fs::write(&partial, data.to_dds()?)?))
+            .and_then(|_| Ok(std::fs::rename(&partial, &file)?));
+        if let Err(error) = written {
+            log::warn!("Could not cache the compressed {path}: {error:#}");
+        }
+    }
+    Ok(data)
+}
+
+/// How many of the queued uploads fit into `budget` bytes this frame. The first one
+/// always does, so an image larger than the budget still gets uploaded.
+pub fn uploads_within(sizes: impl IntoIterator<Item = usize>, budget: usize) -> usize {
+    let mut spent = 0;
+    let mut count = 0;
+    for size in sizes {
+        if count > 0 && spent + size > budget {
+            break;
+        }
+        spent += size;
+        count += 1;
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::Storage;
+    use crate::vfs::Source;
+    use std::borrow::Cow;
+    use std::time::Duration;
+
+    // good.png is a 3x2 image, bad.png is not an image at all
+    struct Images;
+    impl Source for Images {
+        fn read(&self, path: &str) -> std::io::Result<Cow<'static, [u8]>> {
+            match path {
+                "good.png" => {
+                    let image = image::RgbaImage::from_pixel(3, 2, image::Rgba([10, 20, 30, 255]));
+                    let mut bytes = std::io::Cursor::new(Vec::new());
+                    image.write_to(&mut bytes, image::ImageFormat::Png).unwrap();
+                    Ok(Cow::Owned(bytes.into_inner()))
+                }
+                "bad.png" => Ok(Cow::Borrowed(b"not an image")),
+                _ => Err(std::io::ErrorKind::NotFound.into()),
+            }
+        }
+
+        fn contains(&self, path: &str) -> bool {
+            self.read(path).is_ok()
+        }
+    }
+
+    #[test]
+    fn images_are_read_and_decoded_on_the_workers() {
+        let mut vfs = Vfs::default();
+        vfs.mount(Images);
+        let vfs = Arc::new(vfs);
+        let mut textures = Storage::<Texture>::default();
+        let loader = Loader::with_workers(2, false);
+        let handles = ["good.png", "bad.png", "missing.png"].map(|path| {
+            let handle = textures.reserve(Some(path.to_string()));
+            loader.load(handle.clone(), path, TextureKind::Color, &vfs);
+            handle
+        });
+
+        let mut results = (0..3)
+            .map(|_| {
+                loader
+                    .decoded
+                    .recv_timeout(Duration::from_secs(10))
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        results.sort_by_key(|decoded| handles.iter().position(|h| *h == decoded.handle));
+
+        let data = results[0].data.as_ref().unwrap();
+        assert_eq!((data.width, data.height), (3, 2));
+        assert_eq!(data.format, wgpu::TextureFormat::Rgba8UnormSrgb);
+        assert_eq!(results[0].size(), 3 * 2 * 4);
+        assert_eq!(results[0].path, "good.png");
+        assert!(results[1].data.is_err());
+        assert_eq!(results[1].size(), 0);
+        let error = results[2].data.as_ref().err().unwrap();
+        assert_eq!(format!("{error:#}"), "missing.png not found");
+    }
+
+    #[test]
+    fn uploads_stop_at_the_budget() {
+        assert_eq!(uploads_within([10, 10, 10], 25), 2);
+        assert_eq!(uploads_within([10, 10, 10], 30), 3);
+        assert_eq!(uploads_within([100, 10], 25), 1);
+        assert_eq!(uploads_within([], 25), 0);
+    }
+}
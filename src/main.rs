@@ -1,5 +1,8 @@
 use pollster::block_on;
 use rust_wgpu::run;
 fn main() {
-    block_on(run())
+    if let Err(error) = block_on(run()) {
+        eprintln!("Error: {error}");
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,302 @@
+use crate::assets::{Assets, Handle};
+use crate::texture::Texture;
+
+/// Scalar factors of a glTF metallic-roughness material, multiplied with the matching maps.
+#[derive(Copy, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct MaterialParams {
+    pub base_color_factor: [f32; 4],
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    pub emissive_factor: [f32; 3],
+}
+impl Default for MaterialParams {
+    // Same defaults as glTF
+    fn default() -> Self {
+        Self {
+            base_color_factor: [1.0; 4],
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            emissive_factor: [0.0; 3],
+        }
+    }
+}
+
+// Data uploaded to the material uniform buffer, and to the material table
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    base_color_factor: [f32; 4],
+    emissive_factor: [f32; 3],
+    metallic_factor: f32,
+    roughness_factor: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+    _padding: f32,
+}
+impl From<MaterialParams> for MaterialUniform {
+    fn from(params: MaterialParams) -> Self {
+        Self {
+            base_color_factor: params.base_color_factor,
+            emissive_factor: params.emissive_factor,
+            metallic_factor: params.metallic_factor,
+            roughness_factor: params.roughness_factor,
+            normal_scale: params.normal_scale,
+            occlusion_strength: params.occlusion_strength,
+            _padding: 0.0,
+        }
+    }
+}
+
+/// Texture maps of a material. Missing maps are replaced by neutral 1x1 textures.
+/// Base colour and emissive are sRGB, the others are linear as in glTF.
+#[derive(Clone, Default)]
+pub struct MaterialTextures {
+    pub base_color: Option<Handle<Texture>>,
+    // Roughness in the green channel, metallic in the blue channel
+    pub metallic_roughness: Option<Handle<Texture>>,
+    pub normal: Option<Handle<Texture>>,
+    // Ambient occlusion in the red channel
+    pub occlusion: Option<Handle<Texture>>,
+    pub emissive: Option<Handle<Texture>>,
+}
+
+/// Where the image of a texture is read from, so it can be uploaded again at any time.
+#[derive(Clone)]
+pub struct TextureSource {
+    // Normalized path in the scene's file system, textures are shared by it
+    pub path: String,
+}
+
+/// CPU side description of a material, everything needed to create it on a device.
+#[derive(Clone, Default)]
+pub struct MaterialDesc {
+    pub name: String,
+    pub base_color: Option<TextureSource>,
+    pub metallic_roughness: Option<TextureSource>,
+    pub normal: Option<TextureSource>,
+    pub occlusion: Option<TextureSource>,
+    pub emissive: Option<TextureSource>,
+    pub params: MaterialParams,
+}
+
+pub struct Material {
+    pub name: String,
+    pub params: MaterialParams,
+    // Keeps the textures loaded while the material uses them
+    textures: MaterialTextures,
+    buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl Material {
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("material_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(1),
+                texture_entry(2),
+                texture_entry(3),
+                texture_entry(4),
+                texture_entry(5),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        name: &str,
+        textures: MaterialTextures,
+        params: MaterialParams,
+        assets: &Assets,
+    ) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("material_buffer"),
+            size: size_of::<MaterialUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(
+            &buffer,
+            0,
+            bytemuck::cast_slice(&[MaterialUniform::from(params)]),
+        );
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("material_sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            // Blends between the mips KTX2 and DDS textures come with
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group =
+            create_bind_group(device, layout, name, &buffer, &sampler, &textures, assets);
+
+        Self {
+            name: name.to_string(),
+            params,
+            textures,
+            buffer,
+            sampler,
+            bind_group,
+        }
+    }
+
+    /// Whether the material samples `texture`.
+    pub fn uses(&self, texture: &Handle<Texture>) -> bool {
+        let textures = &self.textures;
+        [
+            &textures.base_color,
+            &textures.metallic_roughness,
+            &textures.normal,
+            &textures.occlusion,
+            &textures.emissive,
+        ]
+        .into_iter()
+        .any(|used| used.as_ref() == Some(texture))
+    }
+
+    /// Textures bound for the five maps as they are now, in binding order.
+    pub fn maps<'a>(&self, assets: &'a Assets) -> [&'a Texture; 5] {
+        maps(&self.textures, assets)
+    }
+
+    /// Like [`Material::maps`], but `None` for the maps the material leaves out.
+    pub fn assigned_maps<'a>(&self, assets: &'a Assets) -> [Option<&'a Texture>; 5] {
+        let textures = &self.textures;
+        let assigned = [
+            textures.base_color.is_some(),
+            textures.metallic_roughness.is_some(),
+            textures.normal.is_some(),
+            textures.occlusion.is_some(),
+            textures.emissive.is_some(),
+        ];
+        let maps = self.maps(assets);
+        std::array::from_fn(|slot| assigned[slot].then_some(maps[slot]))
+    }
+
+    /// Bind group with the textures as they are now, for when one of them finished loading.
+    pub fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        assets: &Assets,
+    ) -> wgpu::BindGroup {
+        create_bind_group(
+            device,
+            layout,
+            &self.name,
+            &self.buffer,
+            &self.sampler,
+            &self.textures,
+            assets,
+        )
+    }
+
+    /// Uploads changed scalar factors without recreating the bind group.
+    pub fn set_params(&mut self, queue: &wgpu::Queue, params: MaterialParams) {
+        self.params = params;
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[MaterialUniform::from(params)]),
+        );
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    name: &str,
+    buffer: &wgpu::Buffer,
+    sampler: &wgpu::Sampler,
+    textures: &MaterialTextures,
+    assets: &Assets,
+) -> wgpu::BindGroup {
+    let views = maps(textures, assets);
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(name),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            },
+            texture_entry(1, views[0]),
+            texture_entry(2, views[1]),
+            texture_entry(3, views[2]),
+            texture_entry(4, views[3]),
+            texture_entry(5, views[4]),
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+        ],
+    })
+}
+
+fn maps<'a>(textures: &MaterialTextures, assets: &'a Assets) -> [&'a Texture; 5] {
+    // Maps that are not loaded show the placeholder, or their neutral value for
+    // data maps so the lighting stays sane
+    let view = |texture: &Option<Handle<Texture>>, default, placeholder| match texture {
+        Some(handle) => assets.textures.get(handle).unwrap_or(placeholder),
+        None => default,
+    };
+    [
+        view(
+            &textures.base_color,
+            &assets.white_srgb,
+            &assets.placeholder,
+        ),
+        view(&textures.metallic_roughness, &assets.white, &assets.white),
+        view(&textures.normal, &assets.flat_normal, &assets.flat_normal),
+        view(&textures.occlusion, &assets.white, &assets.white),
+        view(&textures.emissive, &assets.white_srgb, &assets.white_srgb),
+    ]
+}
+
+fn texture_entry(binding: u32, texture: &Texture) -> wgpu::BindGroupEntry<'_> {
+    wgpu::BindGroupEntry {
+        binding,
+        resource: wgpu::BindingResource::TextureView(&texture.view),
+    }
+}
@@ -0,0 +1,432 @@
+use wgpu::util::DeviceExt;
+
+use crate::assets::{Assets, Handle};
+use crate::material::{Material, MaterialUniform};
+use crate::texture::Texture;
+
+// Most textures the binding array holds, whatever the device allows
+const MAX_BOUND_TEXTURES: u32 = 256;
+// Index of a map the material leaves out, the shader reads the map's neutral value instead
+const NO_MAP: u32 = u32::MAX;
+// Sampled textures of the light bind group, they count against the same per stage limit
+const LIGHT_TEXTURES: u32 = 4;
+
+// Row of the material table, must match MaterialEntry in the shader_material_*.wgsl files
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialEntry {
+    params: MaterialUniform,
+    // Per map, the layer in its texture array or the index in the binding array, or NO_MAP
+    textures: [u32; 5],
+    _padding: [u32; 3],
+}
+
+/// How the material table binds the textures of the materials.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TableKind {
+    /// One texture array per map, so every texture of a map needs the same size, format
+    /// and mip count.
+    TextureArrays,
+    /// Every texture in one binding array with room for `capacity` of them.
+    BindingArray { capacity: u32 },
+}
+
+impl TableKind {
+    /// A binding array where the device can index one per fragment, texture arrays otherwise.
+    /// `None` when fragment shaders cannot read storage buffers.
+    pub fn for_device(device: &wgpu::Device) -> Option<Self> {
+        let limits = device.limits();
+        if limits.max_storage_buffers_per_shader_stage == 0 {
+            return None;
+        }
+        let indexing = wgpu::Features::TEXTURE_BINDING_ARRAY
+            | wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING;
+        let capacity = limits
+            .max_sampled_textures_per_shader_stage
+            .saturating_sub(LIGHT_TEXTURES)
+            .min(MAX_BOUND_TEXTURES);
+        if device.features().contains(indexing) && capacity > 0 {
+            Some(Self::BindingArray { capacity })
+        } else {
+            Some(Self::TextureArrays)
+        }
+    }
+}
+
+/// Every material of the scene in one storage buffer, with their maps in texture arrays or
+/// a binding array. Instances carry the index of their material, so the whole scene is
+/// drawn at once instead of rebinding a material for each batch.
+pub struct MaterialTable {
+    kind: TableKind,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    // None until built, and while the materials do not fit the table
+    bind_group: Option<wgpu::BindGroup>,
+}
+
+impl MaterialTable {
+    pub fn new(device: &wgpu::Device, kind: TableKind) -> Self {
+        let storage = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let texture_entry = |binding, view_dimension, count| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                multisampled: false,
+                view_dimension,
+            },
+            count,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let entries = match kind {
+            TableKind::TextureArrays => {
+                let array = wgpu::TextureViewDimension::D2Array;
+                vec![
+                    storage,
+                    texture_entry(1, array, None),
+                    texture_entry(2, array, None),
+                    texture_entry(3, array, None),
+                    texture_entry(4, array, None),
+                    texture_entry(5, array, None),
+                    sampler_entry(6),
+                ]
+            }
+            TableKind::BindingArray { capacity } => vec![
+                storage,
+                texture_entry(1, wgpu::TextureViewDimension::D2, capacity.try_into().ok()),
+                sampler_entry(2),
+            ],
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("material_table_layout"),
+            entries: &entries,
+        });
+
+        // Same as the one of each material
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("material_table_sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            kind,
+            layout,
+            sampler,
+            bind_group: None,
+        }
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    /// The lit shader with the material bindings of this kind of table.
+    pub fn shader_source(&self) -> String {
+        let material = match self.kind {
+            TableKind::TextureArrays => include_str!("shader_material_arrays.wgsl"),
+            TableKind::BindingArray { .. } => include_str!("shader_material_bindless.wgsl"),
+        };
+        [include_str!("shader_lit.wgsl"), material].join("\n")
+    }
+
+    /// Bind group of the table at group 0, `None` when it has to be drawn material by material.
+    pub fn bind_group(&self) -> Option<&wgpu::BindGroup> {
+        self.bind_group.as_ref()
+    }
+
+    /// Builds the table from `materials` and the textures they have now. Leaves it empty when
+    /// a material is not loaded or their textures do not fit this kind of table.
+    pub fn rebuild(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        assets: &Assets,
+        materials: &[Handle<Material>],
+    ) {
+        self.bind_group = None;
+        let Some(materials) = materials
+            .iter()
+            .map(|handle| assets.materials.get(handle))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return;
+        };
+        if materials.is_empty() {
+            return;
+        }
+        let maps = materials
+            .iter()
+            .map(|material| material.maps(assets))
+            .collect::<Vec<_>>();
+        let same = |a: &&Texture, b: &&Texture| std::ptr::eq(*a, *b);
+
+        let bind_group = match self.kind {
+            TableKind::TextureArrays => {
+                // Only the maps materials have, so the 1x1 defaults do not need to match them
+                let assigned = materials
+                    .iter()
+                    .map(|material| material.assigned_maps(assets))
+                    .collect::<Vec<_>>();
+                let slots: [_; 5] = std::array::from_fn(|slot| {
+                    index_distinct(assigned.iter().map(|textures| textures[slot]), same)
+                });
+                let max_layers = device.limits().max_texture_array_layers as usize;
+                let fits = slots.iter().all(|(textures, _)| {
+                    textures.len() <= max_layers
+                        && textures
+                            .iter()
+                            .all(|texture| same_shape(texture, textures[0]))
+                });
+                if !fits {
+                    log::warn!(
+                        "Textures of a material map differ in size, format or mips, \
+                         drawing materials one by one"
+                    );
+                    return;
+                }
+
+                let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("material_table_encoder"),
+                });
+                // A map no material has still needs an array, the default fills it
+                let views = std::array::from_fn::<_, 5, _>(|slot| match &slots[slot].0 {
+                    textures if textures.is_empty() => {
+                        stack(device, &mut encoder, &[maps[0][slot]])
+                    }
+                    textures => stack(device, &mut encoder, textures),
+                });
+                queue.submit(std::iter::once(encoder.finish()));
+
+                let indices = (0..materials.len())
+                    .map(|material| slots.each_ref().map(|(_, indices)| indices[material]))
+                    .collect::<Vec<_>>();
+                let view_entry = |binding: u32| wgpu::BindGroupEntry {
+                    binding,
+                    resource: wgpu::BindingResource::TextureView(&views[binding as usize - 1]),
+                };
+                let entries = self.entries(device, &materials, &indices);
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("material_table_bind_group"),
+                    layout: &self.layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: entries.as_entire_binding(),
+                        },
+                        view_entry(1),
+                        view_entry(2),
+                        view_entry(3),
+                        view_entry(4),
+                        view_entry(5),
+                        wgpu::BindGroupEntry {
+                            binding: 6,
+                            resource: wgpu::BindingResource::Sampler(&self.sampler),
+                        },
+                    ],
+                });
+                bind_group
+            }
+            TableKind::BindingArray { capacity } => {
+                let (textures, flat) =
+                    index_distinct(maps.iter().flatten().map(|&texture| Some(texture)), same);
+                if textures.len() > capacity as usize {
+                    log::warn!(
+                        "Materials use {} textures, more than the {capacity} a binding array \
+                         holds here, drawing materials one by one",
+                        textures.len()
+                    );
+                    return;
+                }
+
+                // Every element has to be bound, the unused ones repeat the first texture
+                let views = (0..capacity as usize)
+                    .map(|i| &textures.get(i).unwrap_or(&textures[0]).view)
+                    .collect::<Vec<_>>();
+                let indices = flat
+                    .chunks_exact(5)
+                    .map(|chunk| chunk.try_into().unwrap())
+                    .collect::<Vec<_>>();
+                let entries = self.entries(device, &materials, &indices);
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("material_table_bind_group"),
+                    layout: &self.layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: entries.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureViewArray(&views),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::Sampler(&self.sampler),
+                        },
+                    ],
+                });
+                bind_group
+            }
+        };
+        self.bind_group = Some(bind_group);
+    }
+
+    fn entries(
+        &self,
+        device: &wgpu::Device,
+        materials: &[&Material],
+        indices: &[[u32; 5]],
+    ) -> wgpu::Buffer {
+        let entries = materials
+            .iter()
+            .zip(indices)
+            .map(|(material, &textures)| MaterialEntry {
+                params: material.params.into(),
+                textures,
+                _padding: [0; 3],
+            })
+            .collect::<Vec<_>>();
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("material_table_buffer"),
+            contents: bytemuck::cast_slice(&entries),
+            usage: wgpu::BufferUsages::STORAGE,
+        })
+    }
+}
+
+// The distinct items in the order they first appear, and the index among them of each item,
+// NO_MAP where there is none
+fn index_distinct<T>(
+    items: impl IntoIterator<Item = Option<T>>,
+    same: impl Fn(&T, &T) -> bool,
+) -> (Vec<T>, Vec<u32>) {
+    let mut distinct: Vec<T> = Vec::new();
+    let indices = items
+        .into_iter()
+        .map(|item| {
+            let Some(item) = item else {
+                return NO_MAP;
+            };
+            match distinct.iter().position(|seen| same(seen, &item)) {
+                Some(index) => index as u32,
+                None => {
+                    distinct.push(item);
+                    distinct.len() as u32 - 1
+                }
+            }
+        })
+        .collect();
+    (distinct, indices)
+}
+
+fn same_shape(a: &Texture, b: &Texture) -> bool {
+    let (a, b) = (&a.texture, &b.texture);
+    a.size() == b.size() && a.format() == b.format() && a.mip_level_count() == b.mip_level_count()
+}
+
+// Copies textures of the same shape into the layers of one array texture
+fn stack(
+    device: &wgpu::Device,
+    encoder: &mut wgpu::CommandEncoder,
+    textures: &[&Texture],
+) -> wgpu::TextureView {
+    let first = &textures[0].texture;
+    let size = wgpu::Extent3d {
+        // GL makes single layer textures plain 2D ones, which cannot be sampled as arrays
+        depth_or_array_layers: textures.len().max(2) as u32,
+        ..first.size()
+    };
+    let array = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("material_texture_array"),
+        size,
+        mip_level_count: first.mip_level_count(),
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: first.format(),
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+
+    for (layer, texture) in textures.iter().enumerate() {
+        for level in 0..first.mip_level_count() {
+            // Block compressed levels are copied in whole blocks
+            let extent = first
+                .size()
+                .mip_level_size(level, wgpu::TextureDimension::D2)
+                .physical_size(first.format());
+            encoder.copy_texture_to_texture(
+                wgpu::TexelCopyTextureInfo {
+                    texture: &texture.texture,
+                    mip_level: level,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::TexelCopyTextureInfo {
+                    texture: &array,
+                    mip_level: level,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                extent,
+            );
+        }
+    }
+
+    array.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distinct_items_keep_their_first_index() {
+        let items = [
+            Some("brick"),
+            Some("tree"),
+            None,
+            Some("brick"),
+            Some("white"),
+        ];
+        let (distinct, indices) = index_distinct(items, |a, b| a == b);
+        assert_eq!(distinct, ["brick", "tree", "white"]);
+        assert_eq!(indices, [0, 1, NO_MAP, 0, 2]);
+
+        let (distinct, indices) = index_distinct([None::<u8>, None], |a, b| a == b);
+        assert!(distinct.is_empty());
+        assert_eq!(indices, [NO_MAP; 2]);
+    }
+
+    #[test]
+    fn entries_match_the_shader_layout() {
+        // MaterialUniform is 48 bytes and the row is padded to the struct's 16 byte alignment
+        assert_eq!(size_of::<MaterialEntry>(), 80);
+    }
+}
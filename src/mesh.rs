@@ -0,0 +1,177 @@
+use cgmath::{InnerSpace, Vector3};
+
+use wgpu::util::DeviceExt;
+
+use crate::Vertex;
+
+/// Vertex and index buffers of one mesh on the GPU.
+pub struct Mesh {
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub num_indices: u32,
+}
+
+impl Mesh {
+    pub fn new(device: &wgpu::Device, name: &str, vertices: &[Vertex], indices: &[u16]) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{name}_vertices")),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{name}_indices")),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        Self {
+            vertex_buffer,
+            index_buffer,
+            num_indices: indices.len() as u32,
+        }
+    }
+}
+
+/// Fills in the `tangent` of every vertex from positions, normals and texture coordinates.
+///
+/// Follows MikkTSpace: per triangle tangents are projected onto the vertex normal and
+/// averaged weighted by the corner angle, then orthonormalized. The `w` component holds the
+/// handedness, so the bitangent is `cross(normal, tangent.xyz) * tangent.w`. Texture coordinates
+/// are flipped to a bottom-left origin first like glTF exporters do, so normal maps whose
+/// green channel points up the image are lit correctly.
+pub fn generate_tangents(vertices: &mut [Vertex], indices: &[u16]) {
+    let mut tangents = vec![Vector3::new(0.0, 0.0, 0.0); vertices.len()];
+    let mut bitangents = vec![Vector3::new(0.0, 0.0, 0.0); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let corners = [
+            triangle[0] as usize,
+            triangle[1] as usize,
+            triangle[2] as usize,
+        ];
+        let position = |i: usize| Vector3::from(vertices[corners[i]].position);
+        let uv = |i: usize| {
+            let [u, v] = vertices[corners[i]].tex_coords;
+            (u, 1.0 - v)
+        };
+
+        let edge1 = position(1) - position(0);
+        let edge2 = position(2) - position(0);
+        let (du1, dv1) = (uv(1).0 - uv(0).0, uv(1).1 - uv(0).1);
+        let (du2, dv2) = (uv(2).0 - uv(0).0, uv(2).1 - uv(0).1);
+
+        let det = du1 * dv2 - du2 * dv1;
+        if det.abs() < f32::EPSILON {
+            // Degenerate texture mapping, leave it to the fallback below
+            continue;
+        }
+        let r = 1.0 / det;
+        let tangent = (edge1 * dv2 - edge2 * dv1) * r;
+        let bitangent = (edge2 * du1 - edge1 * du2) * r;
+
+        for i in 0..3 {
+            let to_next = position((i + 1) % 3) - position(i);
+            let to_prev = position((i + 2) % 3) - position(i);
+            if to_next.magnitude2() == 0.0 || to_prev.magnitude2() == 0.0 {
+                continue;
+            }
+            let angle = to_next
+                .normalize()
+                .dot(to_prev.normalize())
+                .clamp(-1.0, 1.0)
+                .acos();
+
+            let normal = Vector3::from(vertices[corners[i]].normal);
+            let projected = tangent - normal * normal.dot(tangent);
+            if projected.magnitude2() > 0.0 {
+                tangents[corners[i]] += projected.normalize() * angle;
+            }
+            bitangents[corners[i]] += bitangent * angle;
+        }
+    }
+
+    for ((vertex, tangent), bitangent) in vertices.iter_mut().zip(tangents).zip(bitangents) {
+        let normal = Vector3::from(vertex.normal);
+
+        // Gram-Schmidt against the normal, or any perpendicular vector if nothing was accumulated
+        let mut tangent = tangent - normal * normal.dot(tangent);
+        if tangent.magnitude2() < f32::EPSILON {
+            let axis = if normal.x.abs() < 0.9 {
+                Vector3::unit_x()
+            } else {
+                Vector3::unit_y()
+            };
+            tangent = axis - normal * normal.dot(axis);
+        }
+        let tangent = tangent.normalize();
+
+        let handedness = if normal.cross(tangent).dot(bitangent) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+
+        vertex.tangent = [tangent.x, tangent.y, tangent.z, handedness];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Quad facing +Z with the image's top left at the top left, as glTF lays it out
+    fn quad(tex_coords: [[f32; 2]; 4]) -> Vec<Vertex> {
+        let positions = [
+            [-1.0, -1.0, 0.0],
+            [1.0, -1.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [-1.0, 1.0, 0.0],
+        ];
+        positions
+            .iter()
+            .zip(tex_coords)
+            .map(|(&position, tex_coords)| Vertex {
+                position,
+                tex_coords,
+                normal: [0.0, 0.0, 1.0],
+                tangent: [0.0; 4],
+            })
+            .collect()
+    }
+
+    const INDICES: &[u16] = &[0, 1, 2, 2, 3, 0];
+
+    fn assert_tangent(vertices: &[Vertex], expected: [f32; 4]) {
+        for vertex in vertices {
+            for (value, expected) in vertex.tangent.iter().zip(expected) {
+                assert!((value - expected).abs() < 1e-5, "{:?}", vertex.tangent);
+            }
+        }
+    }
+
+    #[test]
+    fn tangents_follow_u_and_v_points_up_the_image() {
+        let mut vertices = quad([[0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0]]);
+        generate_tangents(&mut vertices, INDICES);
+        // The bitangent, cross(normal, tangent) * w, is +Y where the image's top is
+        assert_tangent(&vertices, [1.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn mirrored_texture_coordinates_flip_the_handedness() {
+        let mut vertices = quad([[1.0, 1.0], [0.0, 1.0], [0.0, 0.0], [1.0, 0.0]]);
+        generate_tangents(&mut vertices, INDICES);
+        assert_tangent(&vertices, [-1.0, 0.0, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn degenerate_texture_coordinates_get_a_perpendicular_tangent() {
+        let mut vertices = quad([[0.5, 0.5]; 4]);
+        generate_tangents(&mut vertices, INDICES);
+        for vertex in &vertices {
+            let tangent = Vector3::new(vertex.tangent[0], vertex.tangent[1], vertex.tangent[2]);
+            assert!((tangent.magnitude() - 1.0).abs() < 1e-5);
+            assert!(tangent.dot(Vector3::unit_z()).abs() < 1e-5);
+            assert_eq!(vertex.tangent[3].abs(), 1.0);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::time::Duration;
+
+use crate::gpu_timer::GpuTimer;
+use crate::stats::{FrameStats, HISTORY_LEN};
+
+// Where the graph sits, in normalized device coordinates
+const GRAPH_LEFT: f32 = -0.95;
+const GRAPH_BOTTOM: f32 = -0.95;
+const GRAPH_WIDTH: f32 = 0.8;
+const GRAPH_HEIGHT: f32 = 0.4;
+// Frame time at the top of the graph, longer frames are cut off
+const GRAPH_RANGE: Duration = Duration::from_millis(50);
+
+// Background, three stacked segments per frame and the 60 and 30 fps lines
+const MAX_RECTS: usize = 1 + HISTORY_LEN * 3 + 2;
+
+const BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+const UPDATE: [f32; 4] = [0.2, 0.8, 0.3, 1.0];
+const ENCODE: [f32; 4] = [0.2, 0.4, 1.0, 1.0];
+const REST: [f32; 4] = [0.7, 0.7, 0.7, 1.0];
+// Rest of frames slower than 30 fps, so hitches stand out
+const HITCH: [f32; 4] = [1.0, 0.3, 0.2, 1.0];
+const TARGET_LINE: [f32; 4] = [1.0, 1.0, 1.0, 0.5];
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct Rect {
+    // Lower left and upper right corner
+    bounds: [f32; 4],
+    color: [f32; 4],
+}
+impl Rect {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: size_of::<Rect>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// Bar graph of the recent frame times, drawn over the finished frame.
+/// Each bar stacks update time, encode time and the rest of the frame.
+pub struct FrameGraph {
+    pipeline: wgpu::RenderPipeline,
+    rect_buffer: wgpu::Buffer,
+}
+
+impl FrameGraph {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("overlay_shader_module"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shader_overlay.wgsl").into()),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("overlay_pipeline_layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("overlay_pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader_module,
+                entry_point: Some("vs_main"),
+                buffers: &[Rect::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader_module,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let rect_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("overlay_rect_buffer"),
+            size: (MAX_RECTS * size_of::<Rect>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            pipeline,
+            rect_buffer,
+        }
+    }
+
+    /// Draws the graph on top of `view` in its own pass.
+    pub fn render(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        stats: &FrameStats,
+        timer: Option<&mut GpuTimer>,
+    ) {
+        let rects = graph_rects(stats);
+        queue.write_buffer(&self.rect_buffer, 0, bytemuck::cast_slice(&rects));
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("overlay_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: timer.and_then(|timer| timer.pass("overlay")),
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, self.rect_buffer.slice(..));
+        render_pass.draw(0..4, 0..rects.len() as u32);
+    }
+}
+
+fn graph_rects(stats: &FrameStats) -> Vec<Rect> {
+    let height = |duration: Duration| {
+        (duration.as_secs_f32() / GRAPH_RANGE.as_secs_f32()).min(1.0) * GRAPH_HEIGHT
+    };
+    let rect = |left: f32, bottom: f32, width: f32, top: f32, color| Rect {
+        bounds: [
+            left,
+            GRAPH_BOTTOM + bottom,
+            left + width,
+            GRAPH_BOTTOM + top,
+        ],
+        color,
+    };
+
+    let mut rects = Vec::with_capacity(MAX_RECTS);
+    rects.push(rect(GRAPH_LEFT, 0.0, GRAPH_WIDTH, GRAPH_HEIGHT, BACKGROUND));
+
+    // Newest frame on the right
+    let bar_width = GRAPH_WIDTH / HISTORY_LEN as f32;
+    let first_bar = HISTORY_LEN - stats.history().len();
+    for (index, timing) in stats.history().enumerate() {
+        let left = GRAPH_LEFT + (first_bar + index) as f32 * bar_width;
+        let update = height(timing.update);
+        let encode = height(timing.update + timing.encode);
+        let frame = height(timing.frame).max(encode);
+        let rest = if timing.frame > Duration::from_secs_f64(1.0 / 30.0) {
+            HITCH
+        } else {
+            REST
+        };
+        rects.push(rect(left, 0.0, bar_width, update, UPDATE));
+        rects.push(rect(left, update, bar_width, encode, ENCODE));
+        rects.push(rect(left, encode, bar_width, frame, rest));
+    }
+
+    for fps in [60.0, 30.0] {
+        let y = height(Duration::from_secs_f64(1.0 / fps));
+        rects.push(rect(GRAPH_LEFT, y, GRAPH_WIDTH, y + 0.004, TARGET_LINE));
+    }
+
+    rects
+}
@@ -0,0 +1,359 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::vfs::{self, Source};
+
+// Every asset of a build in one file. Layout, all integers little endian:
+//
+//   header   magic "WPAK", version u32, entry count u32, index offset u64
+//   data     the bytes of every entry, one after another
+//   index    per entry: path length u16, path (UTF-8), offset u64, stored size u64,
+//            size u64, compression u8, CRC-32 of the uncompressed bytes u32
+//
+// Paths are normalized like the ones of the vfs. Entries are deflated only when that
+// makes them smaller, already compressed images are stored as they are.
+const MAGIC: &[u8; 4] = b"WPAK";
+const VERSION: u32 = 1;
+const HEADER_SIZE: u64 = 4 + 4 + 4 + 8;
+// Index entry with an empty path, no count in the header can promise more than fit
+const MIN_ENTRY_SIZE: u64 = 2 + 8 + 8 + 8 + 1 + 4;
+
+/// How the bytes of an entry are stored.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Compression {
+    None,
+    Deflate,
+}
+impl Compression {
+    fn from_byte(byte: u8) -> io::Result<Self> {
+        match byte {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Deflate),
+            _ => Err(invalid(format!("unknown compression {byte}"))),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Deflate => 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub offset: u64,
+    pub stored_size: u64,
+    pub size: u64,
+    pub compression: Compression,
+    pub crc: u32,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Writes a pack of `files`, given by path and contents. Each one is deflated when
+/// `compress` is set and that saves space.
+pub fn write<W: Write + Seek>(
+    mut out: W,
+    files: impl IntoIterator<Item = (String, Vec<u8>)>,
+    compress: bool,
+) -> io::Result<W> {
+    out.write_all(&[0; HEADER_SIZE as usize])?;
+
+    let mut index = Vec::new();
+    let mut offset = HEADER_SIZE;
+    for (path, bytes) in files {
+        let path = vfs::normalize(&path);
+        if path.len() > u16::MAX as usize {
+            return Err(invalid(format!("path {path} is too long")));
+        }
+        let crc = crc32fast::hash(&bytes);
+        let deflated = compress.then(|| deflate(&bytes)).transpose()?;
+        let (compression, stored) = match deflated {
+            Some(deflated) if deflated.len() < bytes.len() => (Compression::Deflate, deflated),
+            _ => (Compression::None, bytes.clone()),
+        };
+        out.write_all(&stored)?;
+        let entry = Entry {
+            offset,
+            stored_size: stored.len() as u64,
+            size: bytes.len() as u64,
+            compression,
+            crc,
+        };
+        offset += entry.stored_size;
+        index.push((path, entry));
+    }
+
+    for (path, entry) in &index {
+        out.write_all(&(path.len() as u16).to_le_bytes())?;
+        out.write_all(path.as_bytes())?;
+        out.write_all(&entry.offset.to_le_bytes())?;
+        out.write_all(&entry.stored_size.to_le_bytes())?;
+        out.write_all(&entry.size.to_le_bytes())?;
+        out.write_all(&[entry.compression.to_byte()])?;
+        out.write_all(&entry.crc.to_le_bytes())?;
+    }
+
+    out.seek(SeekFrom::Start(0))?;
+    out.write_all(MAGIC)?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    out.write_all(&(index.len() as u32).to_le_bytes())?;
+    out.write_all(&offset.to_le_bytes())?;
+    out.seek(SeekFrom::End(0))?;
+    Ok(out)
+}
+
+fn deflate(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(bytes)?;
+    encoder.finish()
+}
+
+/// Reads the header and index of a pack.
+pub fn read_index(mut input: impl Read + Seek) -> io::Result<HashMap<String, Entry>> {
+    let mut header = [0; HEADER_SIZE as usize];
+    input.seek(SeekFrom::Start(0))?;
+    input.read_exact(&mut header)?;
+    if &header[0..4] != MAGIC {
+        return Err(invalid("not an asset pack".to_string()));
+    }
+    let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    if version != VERSION {
+        return Err(invalid(format!(
+            "pack version {version}, expected {VERSION}"
+        )));
+    }
+    let count = u32::from_le_bytes(header[8..12].try_into().unwrap());
+    let index_offset = u64::from_le_bytes(header[12..20].try_into().unwrap());
+
+    let len = input.seek(SeekFrom::End(0))?;
+    if index_offset < HEADER_SIZE || index_offset > len {
+        return Err(invalid(format!(
+            "index at {index_offset} lies outside the pack"
+        )));
+    }
+    if u64::from(count) > (len - index_offset) / MIN_ENTRY_SIZE {
+        return Err(invalid(format!("{count} entries do not fit the index")));
+    }
+
+    input.seek(SeekFrom::Start(index_offset))?;
+    let mut input = io::BufReader::new(input);
+    let mut index = HashMap::with_capacity(count as usize);
+    for _ in 0..count {
+        let mut path = vec![0; u16::from_le_bytes(read_array(&mut input)?) as usize];
+        input.read_exact(&mut path)?;
+        let path = String::from_utf8(path).map_err(|_| invalid("path is not UTF-8".into()))?;
+        let entry = Entry {
+            offset: u64::from_le_bytes(read_array(&mut input)?),
+            stored_size: u64::from_le_bytes(read_array(&mut input)?),
+            size: u64::from_le_bytes(read_array(&mut input)?),
+            compression: Compression::from_byte(read_array::<1>(&mut input)?[0])?,
+            crc: u32::from_le_bytes(read_array(&mut input)?),
+        };
+        let end = entry.offset.checked_add(entry.stored_size);
+        if entry.offset < HEADER_SIZE || end.is_none_or(|end| end > index_offset) {
+            return Err(invalid(format!("{path} lies outside the data")));
+        }
+        index.insert(path, entry);
+    }
+    Ok(index)
+}
+
+fn read_array<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Turns the stored bytes of `entry` back into the file, checking them against its CRC.
+pub fn unpack(path: &str, entry: &Entry, stored: &[u8]) -> io::Result<Vec<u8>> {
+    let bytes = match entry.compression {
+        Compression::None => stored.to_vec(),
+        Compression::Deflate => {
+            // One byte past the size is enough to tell the entry is wrong, however big the
+            // stream inflates
+            let mut bytes = Vec::new();
+            flate2::read::DeflateDecoder::new(stored)
+                .take(entry.size.saturating_add(1))
+                .read_to_end(&mut bytes)?;
+            bytes
+        }
+    };
+    if bytes.len() as u64 != entry.size || crc32fast::hash(&bytes) != entry.crc {
+        return Err(invalid(format!(
+            "{path} is corrupt, its checksum does not match"
+        )));
+    }
+    Ok(bytes)
+}
+
+enum Data {
+    Mapped(memmap2::Mmap),
+    // For file systems that cannot be mapped, reads seek to each entry
+    Streamed(Mutex<File>),
+}
+
+/// A pack file mounted as a source of assets.
+pub struct Pack {
+    index: HashMap<String, Entry>,
+    data: Data,
+}
+
+impl Pack {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let index = read_index(&mut file)?;
+        // SAFETY: packs are written once by the packer and only read afterwards, changing
+        // or truncating one while the game runs is not supported.
+        let data = match unsafe { memmap2::Mmap::map(&file) } {
+            Ok(map) => Data::Mapped(map),
+            Err(_) => Data::Streamed(Mutex::new(file)),
+        };
+        Ok(Self { index, data })
+    }
+}
+
+impl Source for Pack {
+    fn read(&self, path: &str) -> io::Result<Cow<'static, [u8]>> {
+        let entry = self
+            .index
+            .get(path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{path} not found")))?;
+        let bytes = match &self.data {
+            Data::Mapped(map) => {
+                let start = entry.offset as usize;
+                let stored = map
+                    .get(start..start + entry.stored_size as usize)
+                    .ok_or_else(|| invalid(format!("{path} lies outside the pack")))?;
+                unpack(path, entry, stored)?
+            }
+            Data::Streamed(file) => {
+                let mut stored = vec![0; entry.stored_size as usize];
+                let mut file = file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                file.seek(SeekFrom::Start(entry.offset))?;
+                file.read_exact(&mut stored)?;
+                unpack(path, entry, &stored)?
+            }
+        };
+        Ok(Cow::Owned(bytes))
+    }
+
+    fn contains(&self, path: &str) -> bool {
+        self.index.contains_key(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn files() -> Vec<(String, Vec<u8>)> {
+        vec![
+            ("textures/../brick.png".to_string(), vec![7; 1000]),
+            ("noise.bin".to_string(), (0..=255).collect()),
+            ("empty".to_string(), Vec::new()),
+        ]
+    }
+
+    fn read_all(pack: &[u8]) -> HashMap<String, Vec<u8>> {
+        read_index(Cursor::new(pack))
+            .unwrap()
+            .into_iter()
+            .map(|(path, entry)| {
+                let start = entry.offset as usize;
+                let stored = &pack[start..start + entry.stored_size as usize];
+                let bytes = unpack(&path, &entry, stored).unwrap();
+                (path, bytes)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn packs_round_trip() {
+        for compress in [false, true] {
+            let pack = write(Cursor::new(Vec::new()), files(), compress)
+                .unwrap()
+                .into_inner();
+            let read = read_all(&pack);
+            assert_eq!(read.len(), 3);
+            assert_eq!(read["brick.png"], vec![7; 1000]);
+            assert_eq!(read["noise.bin"], (0..=255).collect::<Vec<u8>>());
+            assert!(read["empty"].is_empty());
+        }
+    }
+
+    #[test]
+    fn only_entries_that_shrink_are_compressed() {
+        let pack = write(Cursor::new(Vec::new()), files(), true)
+            .unwrap()
+            .into_inner();
+        let index = read_index(Cursor::new(&pack)).unwrap();
+        assert_eq!(index["brick.png"].compression, Compression::Deflate);
+        assert!(index["brick.png"].stored_size < 100);
+        assert_eq!(index["noise.bin"].compression, Compression::None);
+    }
+
+    #[test]
+    fn corruption_is_detected() {
+        let mut pack = write(Cursor::new(Vec::new()), files(), false)
+            .unwrap()
+            .into_inner();
+        let entry = read_index(Cursor::new(&pack)).unwrap()["noise.bin"].clone();
+        pack[entry.offset as usize] ^= 1;
+        let start = entry.offset as usize;
+        let stored = &pack[start..start + entry.stored_size as usize];
+        let error = unpack("noise.bin", &entry, stored).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        assert!(read_index(Cursor::new(b"PNG\0 not a pack at all")).is_err());
+    }
+
+    #[test]
+    fn broken_indices_are_errors() {
+        let pack = write(Cursor::new(Vec::new()), files(), false)
+            .unwrap()
+            .into_inner();
+        let index_offset = u64::from_le_bytes(pack[12..20].try_into().unwrap()) as usize;
+
+        let truncated = &pack[..pack.len() - 10];
+        assert!(read_index(Cursor::new(truncated)).is_err());
+        assert!(read_index(Cursor::new(&pack[..HEADER_SIZE as usize - 1])).is_err());
+
+        let mut huge_count = pack.clone();
+        huge_count[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(read_index(Cursor::new(&huge_count)).is_err());
+
+        let mut index_past_end = pack.clone();
+        index_past_end[12..20].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(read_index(Cursor::new(&index_past_end)).is_err());
+
+        // The first entry's offset, right after its path length and path
+        let path_len = u16::from_le_bytes([pack[index_offset], pack[index_offset + 1]]) as usize;
+        let offset_at = index_offset + 2 + path_len;
+        let mut overflowing = pack.clone();
+        overflowing[offset_at..offset_at + 8].copy_from_slice(&(u64::MAX - 1).to_le_bytes());
+        let error = read_index(Cursor::new(&overflowing)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn oversized_entries_fail_the_checksum() {
+        let pack = write(Cursor::new(Vec::new()), files(), true)
+            .unwrap()
+            .into_inner();
+        let mut entry = read_index(Cursor::new(&pack)).unwrap()["brick.png"].clone();
+        let start = entry.offset as usize;
+        let stored = &pack[start..start + entry.stored_size as usize];
+        entry.size = u64::MAX;
+        assert!(unpack("brick.png", &entry, stored).is_err());
+    }
+}
@@ -0,0 +1,263 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crate::gpu_timer::GpuFrame;
+
+/// Where an event is shown in the trace viewer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Track {
+    Cpu,
+    Gpu,
+}
+impl Track {
+    fn thread_id(self) -> u32 {
+        match self {
+            Track::Cpu => 1,
+            Track::Gpu => 2,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Track::Cpu => "CPU",
+            Track::Gpu => "GPU",
+        }
+    }
+
+    fn category(self) -> &'static str {
+        match self {
+            Track::Cpu => "cpu",
+            Track::Gpu => "gpu",
+        }
+    }
+}
+
+/// A named span of time, measured from when the profiler was created.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TraceEvent {
+    pub name: &'static str,
+    pub track: Track,
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+/// Start of a scope, handed back to [`Profiler::end`]. Holds no borrow, so the code
+/// being timed is free to use the rest of its state.
+#[must_use]
+pub struct Scope {
+    name: &'static str,
+    // None when nothing is being captured
+    start: Option<Instant>,
+}
+
+struct Capture {
+    frames_left: u32,
+    frame_start: Option<Instant>,
+    events: Vec<TraceEvent>,
+}
+
+/// Records named CPU scopes and GPU passes over a number of frames, for writing a
+/// Chrome trace. Scopes cost next to nothing while no capture is running.
+pub struct Profiler {
+    epoch: Instant,
+    capture: RefCell<Option<Capture>>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self {
+            epoch: Instant::now(),
+            capture: RefCell::new(None),
+        }
+    }
+}
+
+impl Profiler {
+    /// Captures the next `frames` frames, replacing a capture in progress.
+    pub fn start_capture(&mut self, frames: u32) {
+        *self.capture.get_mut() = Some(Capture {
+            frames_left: frames.max(1),
+            frame_start: None,
+            events: Vec::new(),
+        });
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capture.borrow().is_some()
+    }
+
+    pub fn scope(&self, name: &'static str) -> Scope {
+        Scope {
+            name,
+            start: self.is_capturing().then(Instant::now),
+        }
+    }
+
+    pub fn end(&self, scope: Scope) {
+        if let Some(start) = scope.start {
+            self.record(scope.name, Track::Cpu, start, start.elapsed());
+        }
+    }
+
+    /// Adds the passes of a frame to the GPU track. They are lined up with the moment
+    /// the frame was submitted, as GPU timestamps have no relation to the CPU clock.
+    pub fn record_gpu(&self, frame: &GpuFrame) {
+        for pass in &frame.passes {
+            self.record(
+                pass.name,
+                Track::Gpu,
+                frame.submitted + pass.start,
+                pass.duration,
+            );
+        }
+    }
+
+    fn record(&self, name: &'static str, track: Track, start: Instant, duration: Duration) {
+        if let Some(capture) = self.capture.borrow_mut().as_mut() {
+            capture.events.push(TraceEvent {
+                name,
+                track,
+                start: start.saturating_duration_since(self.epoch),
+                duration,
+            });
+        }
+    }
+
+    /// Marks the start of a frame. Returns the captured events once the capture has
+    /// covered all of its frames. GPU passes of the last few frames are still in
+    /// flight by then and are left out.
+    pub fn begin_frame(&mut self, now: Instant) -> Option<Vec<TraceEvent>> {
+        let capture = self.capture.get_mut().as_mut()?;
+        if let Some(frame_start) = capture.frame_start {
+            capture.events.push(TraceEvent {
+                name: "frame",
+                track: Track::Cpu,
+                start: frame_start.saturating_duration_since(self.epoch),
+                duration: now.saturating_duration_since(frame_start),
+            });
+            capture.frames_left -= 1;
+            if capture.frames_left == 0 {
+                return self.capture.get_mut().take().map(|capture| capture.events);
+            }
+        }
+        capture.frame_start = Some(now);
+        None
+    }
+}
+
+/// Writes the events in the Chrome `trace_event` JSON format, which about://tracing
+/// and Perfetto can open.
+pub fn write_trace(events: &[TraceEvent], mut writer: impl Write) -> std::io::Result<()> {
+    let micros = |duration: Duration| duration.as_secs_f64() * 1_000_000.0;
+
+    // Scope names are identifiers in the code, nothing in them needs escaping
+    let thread_names = [Track::Cpu, Track::Gpu].map(|track| {
+        format!(
+            "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":{},\"args\":{{\"name\":\"{}\"}}}}",
+            track.thread_id(),
+            track.name(),
+        )
+    });
+    let spans = events.iter().map(|event| {
+        format!(
+            "{{\"name\":\"{}\",\"cat\":\"{}\",\"ph\":\"X\",\"ts\":{:.3},\"dur\":{:.3},\"pid\":1,\"tid\":{}}}",
+            event.name,
+            event.track.category(),
+            micros(event.start),
+            micros(event.duration),
+            event.track.thread_id(),
+        )
+    });
+
+    write!(writer, "{{\"displayTimeUnit\":\"ms\",\"traceEvents\":[")?;
+    for (index, line) in thread_names.into_iter().chain(spans).enumerate() {
+        let separator = if index == 0 { "" } else { "," };
+        write!(writer, "{separator}\n{line}")?;
+    }
+    writeln!(writer, "\n]}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu_timer::GpuPass;
+
+    #[test]
+    fn scopes_are_only_recorded_while_capturing() {
+        let mut profiler = Profiler::default();
+        let start = Instant::now();
+        let scope = profiler.scope("update");
+        profiler.end(scope);
+        assert!(profiler.begin_frame(start).is_none());
+
+        profiler.start_capture(1);
+        assert!(profiler.begin_frame(start).is_none());
+        let scope = profiler.scope("update");
+        profiler.end(scope);
+        let events = profiler
+            .begin_frame(start + Duration::from_millis(16))
+            .unwrap();
+
+        assert_eq!(
+            events.iter().map(|event| event.name).collect::<Vec<_>>(),
+            vec!["update", "frame"]
+        );
+        assert_eq!(events[1].duration, Duration::from_millis(16));
+        assert!(!profiler.is_capturing());
+    }
+
+    #[test]
+    fn gpu_passes_follow_the_submission() {
+        let mut profiler = Profiler::default();
+        profiler.start_capture(1);
+        let submitted = profiler.epoch + Duration::from_millis(5);
+        profiler.record_gpu(&GpuFrame {
+            submitted,
+            passes: vec![GpuPass {
+                name: "scene",
+                start: Duration::from_millis(1),
+                duration: Duration::from_millis(2),
+            }],
+        });
+        let events = profiler.capture.borrow().as_ref().unwrap().events.clone();
+        assert_eq!(
+            events,
+            vec![TraceEvent {
+                name: "scene",
+                track: Track::Gpu,
+                start: Duration::from_millis(6),
+                duration: Duration::from_millis(2),
+            }]
+        );
+    }
+
+    #[test]
+    fn trace_is_chrome_json() {
+        let events = [
+            TraceEvent {
+                name: "update",
+                track: Track::Cpu,
+                start: Duration::from_micros(1500),
+                duration: Duration::from_micros(250),
+            },
+            TraceEvent {
+                name: "scene",
+                track: Track::Gpu,
+                start: Duration::from_millis(2),
+                duration: Duration::from_millis(1),
+            },
+        ];
+        let mut json = Vec::new();
+        write_trace(&events, &mut json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            "{\"displayTimeUnit\":\"ms\",\"traceEvents\":[\n\
+             {\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":1,\"args\":{\"name\":\"CPU\"}},\n\
+             {\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":2,\"args\":{\"name\":\"GPU\"}},\n\
+             {\"name\":\"update\",\"cat\":\"cpu\",\"ph\":\"X\",\"ts\":1500.000,\"dur\":250.000,\"pid\":1,\"tid\":1},\n\
+             {\"name\":\"scene\",\"cat\":\"gpu\",\"ph\":\"X\",\"ts\":2000.000,\"dur\":1000.000,\"pid\":1,\"tid\":2}\n\
+             ]}\n"
+        );
+    }
+}
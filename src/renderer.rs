@@ -0,0 +1,634 @@
+use wgpu::util::DeviceExt;
+
+use crate::assets::{Assets, Handle, Progress};
+use crate::gpu_timer::{GpuFrame, GpuTimer};
+use crate::hot_reload::MaterialUpdate;
+use crate::ibl::Environment;
+use crate::light::LightUniform;
+use crate::material::{Material, MaterialDesc};
+use crate::material_table::{MaterialTable, TableKind};
+use crate::mesh::Mesh;
+use crate::overlay::FrameGraph;
+use crate::profiler::Profiler;
+use crate::scene::Scene;
+use crate::scene_file::CUBE_MESH;
+use crate::shadow::PointShadows;
+use crate::skybox::{Skybox, SkyboxSource};
+use crate::stats::FrameStats;
+use crate::vfs::Vfs;
+use crate::{material_batches, texture, InstanceRaw, RendererError, Vertex};
+
+// Scale of the ambient light coming from the environment map
+const ENVIRONMENT_INTENSITY: f32 = 1.0;
+const ENVIRONMENT_PATH: &str = "environment.hdr";
+
+/// Every GPU resource needed to draw a [`Scene`]. All of it belongs to one device,
+/// so after a device loss the whole renderer is created again from the scene.
+pub struct Renderer {
+    render_pipeline: wgpu::RenderPipeline,
+    assets: Assets,
+    mesh: Handle<Mesh>,
+    instance_buffer: wgpu::Buffer,
+    material_bind_group_layout: wgpu::BindGroupLayout,
+    // One per material of the scene, in the same order
+    materials: Vec<Handle<Material>>,
+    // Draws every instance at once where the device can, with its own pipeline
+    material_table: Option<(MaterialTable, wgpu::RenderPipeline)>,
+    // Set when materials or their textures change, the table is rebuilt once streaming is done
+    table_dirty: bool,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    depth_texture: texture::Texture,
+    light_buffer: wgpu::Buffer,
+    light_bind_group: wgpu::BindGroup,
+    point_shadows: PointShadows,
+    // Lights the scene, and is the skybox unless the scene has its own
+    environment: Environment,
+    skybox: Skybox,
+    frame_graph: FrameGraph,
+    // Only when the device has timestamp queries
+    gpu_timer: Option<GpuTimer>,
+}
+
+impl Renderer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        scene: &Scene,
+    ) -> Result<Self, RendererError> {
+        let mut assets = Assets::new(device, queue, scene.vfs.clone());
+        let mesh = assets.load_mesh(device, CUBE_MESH, &scene.vertices, &scene.indices);
+
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("shader_module"),
+            source: wgpu::ShaderSource::Wgsl(
+                [
+                    include_str!("shader_lit.wgsl"),
+                    include_str!("shader_material_bound.wgsl"),
+                ]
+                .join("\n")
+                .into(),
+            ),
+        });
+
+        let material_bind_group_layout = Material::bind_group_layout(device);
+        let materials = scene
+            .materials
+            .iter()
+            .map(|desc| assets.load_material(device, queue, &material_bind_group_layout, desc))
+            .collect();
+
+        let instance_data = scene
+            .mesh_instances()
+            .iter()
+            .map(|instance| InstanceRaw::new(instance.model, instance.material))
+            .collect::<Vec<_>>();
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Instance Buffer"),
+            contents: bytemuck::cast_slice(&instance_data),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("camera_buffer"),
+            contents: bytemuck::cast_slice(&[scene.camera.uniform()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let camera_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("camera_bind_group_layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("camera_bind_group"),
+            layout: &camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+        });
+
+        let depth_texture = texture::Texture::create_depth_texture(device, config, "depth_texture");
+
+        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("light_buffer"),
+            contents: bytemuck::cast_slice(&[LightUniform::new(
+                &scene.lights(),
+                ENVIRONMENT_INTENSITY,
+                scene.normal_mapping,
+            )]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let point_shadows = PointShadows::new(device);
+
+        let environment = scene
+            .vfs
+            .read(ENVIRONMENT_PATH)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Environment::from_hdr_bytes(device, queue, &bytes))
+            .map_err(RendererError::asset(ENVIRONMENT_PATH))?;
+
+        let light_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("light_bind_group_layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Depth,
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::CubeArray,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::Cube,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::Cube,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+
+        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("light_bind_group"),
+            layout: &light_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&point_shadows.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&point_shadows.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&environment.irradiance_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&environment.prefiltered_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&environment.brdf_lut_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Sampler(&environment.sampler),
+                },
+            ],
+        });
+
+        let skybox_texture = Skybox::load(device, queue, &scene.skybox, &environment, &scene.vfs)
+            .map_err(RendererError::asset("skybox"))?;
+        let skybox = Skybox::new(
+            device,
+            config.format,
+            &camera_bind_group_layout,
+            skybox_texture,
+        );
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("render_pipeline_layout"),
+                bind_group_layouts: &[
+                    &material_bind_group_layout,
+                    &camera_bind_group_layout,
+                    &light_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let render_pipeline = Self::create_render_pipeline(
+            &render_pipeline_layout,
+            device,
+            config.format,
+            &shader_module,
+        );
+
+        let material_table = TableKind::for_device(device).map(|kind| {
+            log::info!("Drawing materials from a table with {kind:?}");
+            let table = MaterialTable::new(device, kind);
+            let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("material_table_shader_module"),
+                source: wgpu::ShaderSource::Wgsl(table.shader_source().into()),
+            });
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("material_table_pipeline_layout"),
+                bind_group_layouts: &[
+                    table.layout(),
+                    &camera_bind_group_layout,
+                    &light_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+            let pipeline =
+                Self::create_render_pipeline(&layout, device, config.format, &shader_module);
+            (table, pipeline)
+        });
+
+        Ok(Self {
+            render_pipeline,
+            assets,
+            mesh,
+            instance_buffer,
+            material_bind_group_layout,
+            materials,
+            material_table,
+            table_dirty: true,
+            camera_buffer,
+            camera_bind_group,
+            depth_texture,
+            light_buffer,
+            light_bind_group,
+            point_shadows,
+            environment,
+            skybox,
+            frame_graph: FrameGraph::new(device, config.format),
+            gpu_timer: GpuTimer::new(device, queue),
+        })
+    }
+
+    fn create_render_pipeline(
+        layout: &wgpu::PipelineLayout,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        shader_module: &wgpu::ShaderModule,
+    ) -> wgpu::RenderPipeline {
+        // Render pipeline object to be returned
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("render_pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader_module,
+                entry_point: Some("vs_main"),
+                buffers: &[Vertex::desc(), InstanceRaw::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader_module,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                // Other modes besides Fill requires Features::NON_FILL_POLYGON_MODE
+                polygon_mode: wgpu::PolygonMode::Fill,
+                // Requires Features::DEPTH_CLIP_CONTROL
+                unclipped_depth: false,
+                // requires Features::CONSERVATIVE_RASTERIZATION
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.depth_texture =
+            texture::Texture::create_depth_texture(device, config, "depth_texture");
+    }
+
+    /// Loads the cube map of a skybox, to be shown with [`Self::set_skybox`].
+    pub fn load_skybox(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        source: &SkyboxSource,
+        vfs: &Vfs,
+    ) -> anyhow::Result<texture::Texture> {
+        Skybox::load(device, queue, source, &self.environment, vfs)
+    }
+
+    /// Shows a cube map from [`Self::load_skybox`] behind the scene.
+    pub fn set_skybox(&mut self, device: &wgpu::Device, texture: texture::Texture) {
+        self.skybox.set_texture(device, texture);
+    }
+
+    /// Makes room for `count` instances, replacing the instance buffer when it is too small.
+    pub fn reserve_instances(&mut self, device: &wgpu::Device, count: usize) {
+        let size = (count * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress;
+        if size <= self.instance_buffer.size() {
+            return;
+        }
+        self.instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            // Leaves room so adding instances one by one does not replace it every time
+            size: size.next_power_of_two(),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+    }
+
+    /// Replaces the materials with `descs`. Each one keeps, updates or replaces a current
+    /// material as its entry in `updates` says, and whatever is no longer used is unloaded.
+    pub fn update_materials(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        descs: &[MaterialDesc],
+        updates: &[MaterialUpdate],
+    ) {
+        let current = std::mem::take(&mut self.materials);
+        self.materials = descs
+            .iter()
+            .zip(updates)
+            .map(|(desc, update)| match *update {
+                MaterialUpdate::Keep(index) => current[index].clone(),
+                MaterialUpdate::Params(index) => {
+                    if let Some(material) = self.assets.materials.get_mut(&current[index]) {
+                        material.set_params(queue, desc.params);
+                    }
+                    current[index].clone()
+                }
+                MaterialUpdate::Upload => {
+                    self.assets
+                        .load_material(device, queue, &self.material_bind_group_layout, desc)
+                }
+            })
+            .collect();
+
+        self.table_dirty = true;
+
+        drop(current);
+        let unloaded = self.assets.collect_unused();
+        if unloaded > 0 {
+            log::debug!("Unloaded {unloaded} unused assets");
+        }
+    }
+
+    /// Uploads textures that finished decoding in the background, within a per frame budget.
+    /// The material table is rebuilt once every texture has loaded.
+    pub fn stream_assets(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let finished = self
+            .assets
+            .update(device, queue, &self.material_bind_group_layout);
+        self.table_dirty |= finished > 0;
+
+        if let Some((table, _)) = &mut self.material_table {
+            if self.table_dirty && self.assets.progress().is_done() {
+                table.rebuild(device, queue, &self.assets, &self.materials);
+                self.table_dirty = false;
+            }
+        }
+    }
+
+    pub fn asset_progress(&self) -> Progress {
+        self.assets.progress()
+    }
+
+    /// Uploads the camera, lights and instances of the scene as of its last interpolation.
+    pub fn update(&self, queue: &wgpu::Queue, scene: &Scene, profiler: &Profiler) {
+        let lights = scene.lights();
+
+        let scope = profiler.scope("upload_camera");
+        queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[scene.camera.uniform()]),
+        );
+        profiler.end(scope);
+
+        let scope = profiler.scope("upload_lights");
+        queue.write_buffer(
+            &self.light_buffer,
+            0,
+            bytemuck::cast_slice(&[LightUniform::new(
+                &lights,
+                ENVIRONMENT_INTENSITY,
+                scene.normal_mapping,
+            )]),
+        );
+        self.point_shadows.update(queue, &lights);
+        profiler.end(scope);
+
+        let scope = profiler.scope("upload_instances");
+        let instance_data = scene
+            .mesh_instances()
+            .iter()
+            .map(|instance| InstanceRaw::new(instance.model, instance.material))
+            .collect::<Vec<_>>();
+        queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&instance_data),
+        );
+        profiler.end(scope);
+    }
+
+    /// Draws the scene into `view`, which must have the format the renderer was created with.
+    /// The frame time graph is drawn on top when `stats` are given.
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        view: &wgpu::TextureView,
+        scene: &Scene,
+        stats: Option<&FrameStats>,
+        profiler: &Profiler,
+    ) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("render_encoder"),
+        });
+
+        let instances = scene.mesh_instances();
+        let mesh = self
+            .assets
+            .meshes
+            .get(&self.mesh)
+            .expect("meshes are uploaded when they are loaded");
+
+        let scope = profiler.scope("encode_shadows");
+        self.point_shadows.render(
+            &mut encoder,
+            &scene.lights(),
+            self.gpu_timer.as_mut(),
+            |render_pass| {
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+                render_pass
+                    .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+                render_pass.draw_indexed(0..mesh.num_indices, 0, 0..instances.len() as _);
+            },
+        );
+        profiler.end(scope);
+
+        let scope = profiler.scope("encode_scene");
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("render_pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(scene.clear_color),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: self
+                    .gpu_timer
+                    .as_mut()
+                    .and_then(|timer| timer.pass("scene")),
+            });
+
+            // The table while it is built and up to date, the materials one by one otherwise
+            let table = self
+                .material_table
+                .as_ref()
+                .filter(|_| !self.table_dirty)
+                .and_then(|(table, pipeline)| Some((table.bind_group()?, pipeline)));
+            render_pass.set_pipeline(match table {
+                Some((_, pipeline)) => pipeline,
+                None => &self.render_pipeline,
+            });
+
+            render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+            render_pass.set_bind_group(2, &self.light_bind_group, &[]);
+
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+
+            if let Some((bind_group, _)) = table {
+                // Each instance picks its material from the table by index
+                render_pass.set_bind_group(0, bind_group, &[]);
+                render_pass.draw_indexed(0..mesh.num_indices, 0, 0..instances.len() as _);
+            } else {
+                // One draw per run of instances sharing a material
+                for (material, instances) in material_batches(&instances) {
+                    let Some(material) = self.assets.materials.get(&self.materials[material])
+                    else {
+                        continue;
+                    };
+                    render_pass.set_bind_group(0, &material.bind_group, &[]);
+                    render_pass.draw_indexed(0..mesh.num_indices, 0, instances);
+                }
+            }
+
+            self.skybox.render(&mut render_pass);
+        }
+        profiler.end(scope);
+
+        if let Some(stats) = stats {
+            let scope = profiler.scope("encode_overlay");
+            self.frame_graph
+                .render(queue, &mut encoder, view, stats, self.gpu_timer.as_mut());
+            profiler.end(scope);
+        }
+
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.resolve(&mut encoder);
+        }
+
+        // Submit will accept anything that implements IntoIter
+        let scope = profiler.scope("submit");
+        queue.submit(std::iter::once(encoder.finish()));
+        profiler.end(scope);
+
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.map();
+        }
+    }
+
+    /// GPU pass timings of the newest frame whose timestamps have been read back,
+    /// `None` when there is none or the device has no timestamp queries.
+    pub fn gpu_frame(&mut self, device: &wgpu::Device) -> Option<GpuFrame> {
+        self.gpu_timer.as_mut()?.collect(device)
+    }
+}
@@ -0,0 +1,126 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use cgmath::{Matrix4, SquareMatrix, Transform as _};
+
+use crate::camera::Camera;
+use crate::graph::{NodeId, SceneGraph};
+use crate::light::PointLight;
+use crate::material::MaterialDesc;
+use crate::skybox::SkyboxSource;
+use crate::vfs::{self, Vfs};
+use crate::{mesh, scene_file, Vertex, INDICES, VERTICES};
+
+/// A mesh node ready to be drawn.
+pub struct MeshInstance {
+    // Index into Scene::materials
+    pub material: usize,
+    pub model: Matrix4<f32>,
+}
+
+/// Everything that is drawn, kept on the CPU. The renderer creates its GPU
+/// resources from this, so they can be rebuilt at any time.
+pub struct Scene {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u16>,
+    // Meshes, lights and the camera, placed relative to each other
+    pub graph: SceneGraph,
+    pub materials: Vec<MaterialDesc>,
+    pub camera: Camera,
+    pub skybox: SkyboxSource,
+    // Where the texture and skybox paths are read from
+    pub vfs: Arc<Vfs>,
+    // Toggled with N to compare shading with and without normal maps
+    pub normal_mapping: bool,
+    // challenge 1
+    pub clear_color: wgpu::Color,
+}
+
+impl Scene {
+    /// The demo scene, with its assets from the default asset directory or the binary.
+    pub fn new(aspect: f32) -> Self {
+        let vfs = Vfs::standard(Path::new(vfs::DEFAULT_ROOT), Vec::new());
+        scene_file::demo(aspect, Arc::new(vfs))
+    }
+
+    /// The built-in cube mesh with no materials and nothing placed in the scene.
+    pub fn empty(aspect: f32, vfs: Arc<Vfs>) -> Self {
+        let mut vertices = VERTICES.to_vec();
+        mesh::generate_tangents(&mut vertices, INDICES);
+
+        Self {
+            vertices,
+            indices: INDICES.to_vec(),
+            graph: SceneGraph::default(),
+            materials: Vec::new(),
+            camera: Camera::default(aspect),
+            skybox: SkyboxSource::default(),
+            vfs,
+            normal_mapping: true,
+            // Challenge 1
+            clear_color: wgpu::Color {
+                r: 0.3,
+                g: 0.3,
+                b: 0.2,
+                a: 1.0,
+            },
+        }
+    }
+
+    /// Remembers the current transforms before a fixed update changes them.
+    pub fn begin_step(&mut self) {
+        self.graph.begin_step();
+    }
+
+    /// Blends the graph and the camera `alpha` of the way from the previous fixed update
+    /// to the current one.
+    pub fn interpolate(&mut self, alpha: f32) {
+        self.graph.interpolate(alpha);
+        let parent = self
+            .camera_node()
+            .map_or(Matrix4::identity(), |id| self.graph.interpolated_world(id));
+        self.camera.interpolate(alpha, parent);
+    }
+
+    /// The node the camera is attached to.
+    pub fn camera_node(&self) -> Option<NodeId> {
+        self.graph
+            .nodes()
+            .find(|(_, node)| node.camera)
+            .map(|(id, _)| id)
+    }
+
+    /// Every node with a mesh, as of the last [`Self::interpolate`], in material order.
+    pub fn mesh_instances(&self) -> Vec<MeshInstance> {
+        let mut instances = self
+            .graph
+            .nodes()
+            .filter_map(|(id, node)| {
+                node.mesh.map(|mesh| MeshInstance {
+                    material: mesh.material,
+                    model: self.graph.interpolated_world(id),
+                })
+            })
+            .collect::<Vec<_>>();
+        // Stable, so instances keep their order within a material batch
+        instances.sort_by_key(|instance| instance.material);
+        instances
+    }
+
+    /// Every node with a light, placed in world space as of the last [`Self::interpolate`].
+    pub fn lights(&self) -> Vec<PointLight> {
+        self.graph
+            .nodes()
+            .filter_map(|(id, node)| {
+                let light = node.light.as_ref()?;
+                Some(PointLight {
+                    position: self
+                        .graph
+                        .interpolated_world(id)
+                        .transform_point(light.position),
+                    ..light.clone()
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,507 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use cgmath::{Deg, Euler, Quaternion, Rad};
+use serde::{Deserialize, Serialize};
+
+use crate::graph::{MeshComponent, NodeId, Transform};
+use crate::light::PointLight;
+use crate::material::{MaterialDesc, MaterialParams, TextureSource};
+use crate::scene::Scene;
+use crate::skybox::SkyboxSource;
+use crate::vfs::{self, Vfs};
+
+// The only mesh so far, every node with a mesh draws it
+pub const CUBE_MESH: &str = "cube";
+
+/// A scene as written in a `.ron` or `.json` file. Paths are relative to the file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SceneFile {
+    pub clear_color: [f64; 4],
+    pub skybox: SkyboxFile,
+    pub normal_mapping: bool,
+    pub camera: CameraFile,
+    pub materials: Vec<MaterialFile>,
+    // Parents come before their children
+    pub nodes: Vec<NodeFile>,
+}
+impl Default for SceneFile {
+    fn default() -> Self {
+        Self {
+            clear_color: [0.3, 0.3, 0.2, 1.0],
+            skybox: SkyboxFile::default(),
+            normal_mapping: true,
+            camera: CameraFile::default(),
+            materials: Vec::new(),
+            nodes: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum SkyboxFile {
+    #[default]
+    Environment,
+    Faces([String; 6]),
+    Equirect(String),
+}
+
+/// Where the camera starts, relative to the node holding it if there is one.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraFile {
+    pub position: [f32; 3],
+    // In degrees
+    pub yaw: f32,
+    pub pitch: f32,
+}
+impl Default for CameraFile {
+    fn default() -> Self {
+        Self {
+            position: [-5.0, 0.0, 0.0],
+            yaw: 0.0,
+            pitch: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MaterialFile {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metallic_roughness: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normal: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub occlusion: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emissive: Option<String>,
+    #[serde(default)]
+    pub params: MaterialParams,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeFile {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    pub translation: [f32; 3],
+    // Euler angles in degrees, applied in x, y, z order
+    pub rotation: [f32; 3],
+    pub scale: [f32; 3],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mesh: Option<MeshFile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub light: Option<LightFile>,
+    // The camera moves with this node
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub camera: bool,
+}
+impl Default for NodeFile {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            parent: None,
+            translation: [0.0; 3],
+            rotation: [0.0; 3],
+            scale: [1.0; 3],
+            mesh: None,
+            light: None,
+            camera: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MeshFile {
+    #[serde(default = "cube_mesh")]
+    pub mesh: String,
+    pub material: String,
+}
+
+fn cube_mesh() -> String {
+    CUBE_MESH.to_string()
+}
+
+/// A point light at the origin of its node.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LightFile {
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub range: f32,
+    pub casts_shadows: bool,
+}
+impl Default for LightFile {
+    fn default() -> Self {
+        Self {
+            color: [1.0; 3],
+            intensity: 10.0,
+            range: 10.0,
+            casts_shadows: false,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    Ron,
+    Json,
+}
+impl Format {
+    /// JSON for `.json` files, RON for anything else.
+    pub fn of(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("json") => Format::Json,
+            _ => Format::Ron,
+        }
+    }
+}
+
+impl SceneFile {
+    pub fn parse(text: &str, format: Format) -> Result<Self> {
+        Ok(match format {
+            Format::Ron => ron::from_str(text)?,
+            Format::Json => serde_json::from_str(text)?,
+        })
+    }
+
+    pub fn to_string(&self, format: Format) -> Result<String> {
+        Ok(match format {
+            Format::Ron => {
+                let config = ron::ser::PrettyConfig::new()
+                    .extensions(ron::extensions::Extensions::IMPLICIT_SOME);
+                ron::ser::to_string_pretty(self, config)?
+            }
+            Format::Json => serde_json::to_string_pretty(self)?,
+        })
+    }
+
+    /// Builds the scene with its assets in `vfs`. Paths are resolved with [`Vfs::resolve`]
+    /// from `dir`, the file's directory in `vfs`. Textures are only checked for, they are
+    /// read once a renderer loads them.
+    pub fn build(&self, vfs: &Arc<Vfs>, dir: &str, aspect: f32) -> Result<Scene> {
+        let mut scene = Scene::empty(aspect, vfs.clone());
+        let [r, g, b, a] = self.clear_color;
+        scene.clear_color = wgpu::Color { r, g, b, a };
+        scene.normal_mapping = self.normal_mapping;
+        scene.skybox = self.skybox.to_source(vfs, dir)?;
+
+        let texture = |path: &Option<String>| -> Result<Option<TextureSource>> {
+            let Some(path) = path else {
+                return Ok(None);
+            };
+            match vfs.resolve(dir, path) {
+                Some(path) => Ok(Some(TextureSource { path })),
+                None => bail!("texture {} not found", vfs::join(dir, path)),
+            }
+        };
+        for material in &self.materials {
+            if scene
+                .materials
+                .iter()
+                .any(|desc| desc.name == material.name)
+            {
+                bail!("there are two materials named {}", material.name);
+            }
+            let desc = (|| -> Result<MaterialDesc> {
+                Ok(MaterialDesc {
+                    name: material.name.clone(),
+                    base_color: texture(&material.base_color)?,
+                    metallic_roughness: texture(&material.metallic_roughness)?,
+                    normal: texture(&material.normal)?,
+                    occlusion: texture(&material.occlusion)?,
+                    emissive: texture(&material.emissive)?,
+                    params: material.params,
+                })
+            })()
+            .with_context(|| format!("material {}", material.name))?;
+            scene.materials.push(desc);
+        }
+
+        let mut ids: HashMap<&str, NodeId> = HashMap::new();
+        for node in &self.nodes {
+            let id = self
+                .add_node(&mut scene, &ids, node)
+                .with_context(|| format!("node {}", node.name))?;
+            ids.insert(&node.name, id);
+        }
+        if scene.graph.nodes().filter(|(_, node)| node.camera).count() > 1 {
+            bail!("only one node can hold the camera");
+        }
+
+        let [x, y, z] = self.camera.position;
+        scene.camera.set_pose(
+            (x, y, z).into(),
+            Rad::from(Deg(self.camera.yaw)).0,
+            Rad::from(Deg(self.camera.pitch)).0,
+        );
+        scene.interpolate(1.0);
+        Ok(scene)
+    }
+
+    fn add_node(
+        &self,
+        scene: &mut Scene,
+        ids: &HashMap<&str, NodeId>,
+        node: &NodeFile,
+    ) -> Result<NodeId> {
+        if node.name.is_empty() {
+            bail!("every node needs a name");
+        }
+        if ids.contains_key(node.name.as_str()) {
+            bail!("the name is used by an earlier node");
+        }
+        let parent = match &node.parent {
+            Some(parent) => Some(
+                *ids.get(parent.as_str())
+                    .ok_or_else(|| anyhow!("parent {parent} has to come before it"))?,
+            ),
+            None => None,
+        };
+        let mesh = match &node.mesh {
+            Some(mesh) => {
+                if mesh.mesh != CUBE_MESH {
+                    bail!("unknown mesh {}, only {CUBE_MESH} is built in", mesh.mesh);
+                }
+                let material = scene
+                    .materials
+                    .iter()
+                    .position(|desc| desc.name == mesh.material)
+                    .ok_or_else(|| anyhow!("unknown material {}", mesh.material))?;
+                Some(MeshComponent { material })
+            }
+            None => None,
+        };
+
+        let [rx, ry, rz] = node.rotation;
+        let transform = Transform {
+            translation: node.translation.into(),
+            rotation: Quaternion::from(Euler::new(Deg(rx), Deg(ry), Deg(rz))),
+            scale: node.scale.into(),
+        };
+        let id = scene.graph.add(&node.name, parent, transform);
+        let graph_node = scene.graph.node_mut(id);
+        graph_node.mesh = mesh;
+        graph_node.light = node.light.as_ref().map(|light| PointLight {
+            position: (0.0, 0.0, 0.0).into(),
+            color: light.color,
+            intensity: light.intensity,
+            range: light.range,
+            casts_shadows: light.casts_shadows,
+        });
+        graph_node.camera = node.camera;
+        Ok(id)
+    }
+
+    /// Describes the scene as it is now, with paths made relative to `dir` where possible.
+    pub fn from_scene(scene: &Scene, dir: &str) -> Self {
+        let relative = |path: &String| vfs::relative(dir, path);
+        let texture =
+            |source: &Option<TextureSource>| source.as_ref().map(|source| relative(&source.path));
+
+        let materials = scene
+            .materials
+            .iter()
+            .map(|desc| MaterialFile {
+                name: desc.name.clone(),
+                base_color: texture(&desc.base_color),
+                metallic_roughness: texture(&desc.metallic_roughness),
+                normal: texture(&desc.normal),
+                occlusion: texture(&desc.occlusion),
+                emissive: texture(&desc.emissive),
+                params: desc.params,
+            })
+            .collect();
+
+        // Depth first, so every parent is written before its children
+        let graph = &scene.graph;
+        let mut stack: Vec<NodeId> = graph
+            .nodes()
+            .filter(|(_, node)| node.parent().is_none())
+            .map(|(id, _)| id)
+            .collect();
+        stack.reverse();
+        let mut nodes = Vec::new();
+        while let Some(id) = stack.pop() {
+            let node = graph.node(id);
+            stack.extend(node.children().iter().rev());
+
+            let local = node.local();
+            let rotation = Euler::from(local.rotation);
+            nodes.push(NodeFile {
+                name: node.name.clone(),
+                parent: node.parent().map(|parent| graph.node(parent).name.clone()),
+                translation: local.translation.into(),
+                rotation: [rotation.x, rotation.y, rotation.z].map(|angle| Deg::from(angle).0),
+                scale: local.scale.into(),
+                mesh: node.mesh.map(|mesh| MeshFile {
+                    mesh: cube_mesh(),
+                    material: scene.materials[mesh.material].name.clone(),
+                }),
+                light: node.light.as_ref().map(|light| LightFile {
+                    color: light.color,
+                    intensity: light.intensity,
+                    range: light.range,
+                    casts_shadows: light.casts_shadows,
+                }),
+                camera: node.camera,
+            });
+        }
+
+        let (position, yaw, pitch) = scene.camera.pose();
+        let color = scene.clear_color;
+        Self {
+            clear_color: [color.r, color.g, color.b, color.a],
+            skybox: match &scene.skybox {
+                SkyboxSource::Environment => SkyboxFile::Environment,
+                SkyboxSource::Faces(paths) => SkyboxFile::Faces(paths.each_ref().map(relative)),
+                SkyboxSource::Equirect(path) => SkyboxFile::Equirect(relative(path)),
+            },
+            normal_mapping: scene.normal_mapping,
+            camera: CameraFile {
+                position: position.into(),
+                yaw: Deg::from(Rad(yaw)).0,
+                pitch: Deg::from(Rad(pitch)).0,
+            },
+            materials,
+            nodes,
+        }
+    }
+}
+
+impl SkyboxFile {
+    // Images are only checked here, the renderer decodes them when it shows the skybox
+    fn to_source(&self, vfs: &Vfs, dir: &str) -> Result<SkyboxSource> {
+        let existing = |path: &String| -> Result<String> {
+            vfs.resolve(dir, path)
+                .ok_or_else(|| anyhow!("skybox image {} does not exist", vfs::join(dir, path)))
+        };
+        Ok(match self {
+            SkyboxFile::Environment => SkyboxSource::Environment,
+            SkyboxFile::Faces(paths) => {
+                let faces = paths.iter().map(existing).collect::<Result<Vec<_>>>()?;
+                SkyboxSource::Faces(faces.try_into().expect("one path per face"))
+            }
+            SkyboxFile::Equirect(path) => SkyboxSource::Equirect(existing(path)?),
+        })
+    }
+}
+
+/// Reads a scene file without loading anything it refers to.
+pub fn read(path: &Path) -> Result<SceneFile> {
+    let text = std::fs::read_to_string(path)?;
+    SceneFile::parse(&text, Format::of(path))
+}
+
+/// Writes the scene as RON, or as JSON when the path ends in `.json`.
+pub fn save(scene: &Scene, path: &Path) -> Result<()> {
+    let dir = scene.vfs.locate(directory(path));
+    let text = SceneFile::from_scene(scene, &dir).to_string(Format::of(path))?;
+    std::fs::write(path, text)?;
+    Ok(())
+}
+
+/// Directory that the paths in the scene file at `path` are relative to.
+pub fn directory(path: &Path) -> &Path {
+    path.parent().unwrap_or(Path::new(""))
+}
+
+/// The scene in `assets/demo.ron`. Its paths are relative to the asset root, and the
+/// files are built into the binary in case `vfs` has no other copy.
+pub fn demo(aspect: f32, vfs: Arc<Vfs>) -> Scene {
+    SceneFile::parse(include_str!("../assets/demo.ron"), Format::Ron)
+        .and_then(|file| file.build(&vfs, "", aspect))
+        .expect("the demo scene only uses built-in assets")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn demo_file() -> SceneFile {
+        SceneFile::parse(include_str!("../assets/demo.ron"), Format::Ron).unwrap()
+    }
+
+    fn build(text: &str) -> Result<Scene> {
+        let file = SceneFile::parse(text, Format::Ron)?;
+        file.build(&Arc::default(), "scenes", 1.0)
+    }
+
+    #[test]
+    fn demo_scene_survives_saving_and_loading() {
+        let file = demo_file();
+        let mut vfs = Vfs::default();
+        vfs.mount(vfs::Embedded::built_in());
+        let scene = file.build(&Arc::new(vfs), "", 1.0).unwrap();
+        let saved = SceneFile::from_scene(&scene, "");
+        assert_eq!(saved, file);
+
+        for format in [Format::Ron, Format::Json] {
+            let text = saved.to_string(format).unwrap();
+            assert_eq!(SceneFile::parse(&text, format).unwrap(), file);
+        }
+    }
+
+    #[test]
+    fn format_follows_the_extension() {
+        assert_eq!(Format::of(Path::new("scene.json")), Format::Json);
+        assert_eq!(Format::of(Path::new("scene.JSON")), Format::Json);
+        assert_eq!(Format::of(Path::new("scene.ron")), Format::Ron);
+        assert_eq!(Format::of(Path::new("scene")), Format::Ron);
+    }
+
+    #[test]
+    fn paths_are_saved_relative_to_the_scene() {
+        let mut scene = Scene::empty(1.0, Arc::default());
+        scene.skybox = SkyboxSource::Equirect("scenes/sky/sky.hdr".to_string());
+        let saved = SceneFile::from_scene(&scene, "scenes");
+        assert_eq!(
+            saved.skybox,
+            SkyboxFile::Equirect("sky/sky.hdr".to_string())
+        );
+    }
+
+    #[test]
+    fn missing_textures_name_the_path() {
+        let file = SceneFile::parse(
+            r#"(materials: [(name: "brick", base_color: Some("brick.png"))])"#,
+            Format::Ron,
+        )
+        .unwrap();
+        let error = file.build(&Arc::default(), "scenes", 1.0).err().unwrap();
+        let message = format!("{error:#}");
+        assert!(message.starts_with("material brick: texture "), "{message}");
+        assert!(message.contains("brick.png"), "{message}");
+    }
+
+    #[test]
+    fn broken_references_are_reported() {
+        let error = build(r#"(nodes: [(name: "cube", mesh: Some((material: "gold")))])"#)
+            .err()
+            .unwrap();
+        assert_eq!(format!("{error:#}"), "node cube: unknown material gold");
+
+        let error = build(r#"(nodes: [(name: "child", parent: Some("root")), (name: "root")])"#)
+            .err()
+            .unwrap();
+        assert_eq!(
+            format!("{error:#}"),
+            "node child: parent root has to come before it"
+        );
+
+        let error = build(r#"(nodes: [(name: "a"), (name: "a")])"#)
+            .err()
+            .unwrap();
+        assert_eq!(
+            format!("{error:#}"),
+            "node a: the name is used by an earlier node"
+        );
+    }
+}
@@ -0,0 +1,205 @@
+// Compute shaders that turn an equirectangular HDR image into the
+// image based lighting maps used by the lit shader
+
+const PI: f32 = 3.14159265359;
+
+struct FilterParams {
+    // Roughness the prefiltered mip is convolved for
+    roughness: f32,
+    // Sample count of the Monte Carlo integrations
+    sample_count: u32,
+    // Resolution of mip 0 of the source cube, to pick the mip to sample from
+    source_size: f32,
+    _padding: f32,
+}
+
+@group(0) @binding(0)
+var src_equirect: texture_2d<f32>;
+@group(0) @binding(1)
+var src_sampler: sampler;
+@group(0) @binding(2)
+var src_cube: texture_cube<f32>;
+@group(0) @binding(3)
+var dst_faces: texture_storage_2d_array<rgba16float, write>;
+@group(0) @binding(4)
+var dst_lut: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(5)
+var<uniform> params: FilterParams;
+
+// Direction through the centre of a texel of a cube face, using wgpu's face order +X, -X, +Y, -Y, +Z, -Z
+fn cube_direction(id: vec3<u32>, size: vec2<u32>) -> vec3<f32> {
+    let uv = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(size) * 2.0 - 1.0;
+    switch id.z {
+        case 0u: { return normalize(vec3<f32>(1.0, -uv.y, -uv.x)); }
+        case 1u: { return normalize(vec3<f32>(-1.0, -uv.y, uv.x)); }
+        case 2u: { return normalize(vec3<f32>(uv.x, 1.0, uv.y)); }
+        case 3u: { return normalize(vec3<f32>(uv.x, -1.0, -uv.y)); }
+        case 4u: { return normalize(vec3<f32>(uv.x, -uv.y, 1.0)); }
+        default: { return normalize(vec3<f32>(-uv.x, -uv.y, -1.0)); }
+    }
+}
+
+// Orthonormal basis around n
+fn tangent_frame(n: vec3<f32>) -> mat3x3<f32> {
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if abs(n.y) > 0.999 {
+        up = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let tangent = normalize(cross(up, n));
+    let bitangent = cross(n, tangent);
+    return mat3x3<f32>(tangent, bitangent, n);
+}
+
+fn radical_inverse_vdc(index: u32) -> f32 {
+    var bits = (index << 16u) | (index >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return f32(bits) * 2.3283064365386963e-10;
+}
+
+fn hammersley(index: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(index) / f32(count), radical_inverse_vdc(index));
+}
+
+// GGX distributed half vector in tangent space
+fn importance_sample_ggx(xi: vec2<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let denom = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * denom * denom);
+}
+
+// Schlick-GGX with the k remapping used for image based lighting
+fn geometry_smith_ibl(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = roughness * roughness / 2.0;
+    let ggx_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let ggx_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return ggx_v * ggx_l;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn equirect_to_cube(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dst_faces);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+
+    let dir = cube_direction(id, size);
+    let uv = vec2<f32>(atan2(dir.z, dir.x) / (2.0 * PI) + 0.5, acos(clamp(dir.y, -1.0, 1.0)) / PI);
+    // A face spans a quarter of the image's width, pick the mip with about as many texels
+    let face_texels = f32(textureDimensions(src_equirect).x) / 4.0;
+    let lod = max(log2(face_texels / f32(size.x)), 0.0);
+    let color = textureSampleLevel(src_equirect, src_sampler, uv, lod);
+    textureStore(dst_faces, id.xy, id.z, vec4<f32>(color.rgb, 1.0));
+}
+
+// Cosine weighted convolution of the environment for diffuse lighting
+@compute @workgroup_size(8, 8, 1)
+fn irradiance(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dst_faces);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+
+    let frame = tangent_frame(cube_direction(id, size));
+
+    // A low mip is plenty for such a wide filter and keeps the sample count down
+    let lod = max(log2(params.source_size) - 5.0, 0.0);
+    let delta = 0.05;
+    var sum = vec3<f32>(0.0);
+    var count = 0.0;
+    for (var phi = 0.0; phi < 2.0 * PI; phi += delta) {
+        for (var theta = 0.0; theta < 0.5 * PI; theta += delta) {
+            let local = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let color = textureSampleLevel(src_cube, src_sampler, frame * local, lod).rgb;
+            sum += color * cos(theta) * sin(theta);
+            count += 1.0;
+        }
+    }
+
+    textureStore(dst_faces, id.xy, id.z, vec4<f32>(PI * sum / count, 1.0));
+}
+
+// GGX convolution of the environment for one roughness level of the specular mip chain
+@compute @workgroup_size(8, 8, 1)
+fn prefilter(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dst_faces);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+
+    // Split sum assumption: view direction equals normal equals reflection
+    let n = cube_direction(id, size);
+    let frame = tangent_frame(n);
+    let roughness = params.roughness;
+
+    if roughness == 0.0 {
+        textureStore(dst_faces, id.xy, id.z, textureSampleLevel(src_cube, src_sampler, n, 0.0));
+        return;
+    }
+
+    let texel_solid_angle = 4.0 * PI / (6.0 * params.source_size * params.source_size);
+    var sum = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < params.sample_count; i += 1u) {
+        let h = frame * importance_sample_ggx(hammersley(i, params.sample_count), roughness);
+        let l = normalize(2.0 * dot(n, h) * h - n);
+        let n_dot_l = dot(n, l);
+        if n_dot_l > 0.0 {
+            // Sample a blurrier mip where samples are sparse to avoid bright speckles
+            let n_dot_h = max(dot(n, h), 0.0);
+            let pdf = distribution_ggx(n_dot_h, roughness) / 4.0 + 0.0001;
+            let sample_solid_angle = 1.0 / (f32(params.sample_count) * pdf);
+            let lod = max(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0);
+
+            sum += textureSampleLevel(src_cube, src_sampler, l, lod).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+
+    textureStore(dst_faces, id.xy, id.z, vec4<f32>(sum / max(weight, 0.0001), 1.0));
+}
+
+// Scale and bias to F0 of the specular split sum, indexed by n.v and roughness
+@compute @workgroup_size(8, 8, 1)
+fn brdf_lut(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dst_lut);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+
+    let n_dot_v = (f32(id.x) + 0.5) / f32(size.x);
+    let roughness = (f32(id.y) + 0.5) / f32(size.y);
+    let v = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < params.sample_count; i += 1u) {
+        let h = importance_sample_ggx(hammersley(i, params.sample_count), roughness);
+        let l = normalize(2.0 * dot(v, h) * h - v);
+        let n_dot_l = max(l.z, 0.0);
+        let n_dot_h = max(h.z, 0.0);
+        let v_dot_h = max(dot(v, h), 0.0);
+
+        if n_dot_l > 0.0 {
+            let g = geometry_smith_ibl(n_dot_v, n_dot_l, roughness);
+            let g_vis = g * v_dot_h / (n_dot_h * n_dot_v);
+            let fc = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fc) * g_vis;
+            bias += fc * g_vis;
+        }
+    }
+
+    let count = f32(params.sample_count);
+    textureStore(dst_lut, id.xy, vec4<f32>(scale / count, bias / count, 0.0, 1.0));
+}
@@ -0,0 +1,251 @@
+// Vertex shader
+
+// Camera uniform holds the camera view projection data
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_sky_view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Must match MAX_POINT_LIGHTS in light.rs
+const MAX_POINT_LIGHTS: u32 = 4u;
+
+struct PointLight {
+    position: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    // Cube index in t_shadow, negative when the light casts no shadows
+    shadow_index: i32,
+}
+
+struct LightUniform {
+    lights: array<PointLight, MAX_POINT_LIGHTS>,
+    environment_intensity: f32,
+    count: u32,
+    normal_mapping: u32,
+}
+@group(2) @binding(0)
+var<uniform> light_data: LightUniform;
+@group(2) @binding(1)
+var t_shadow: texture_depth_cube_array;
+@group(2) @binding(2)
+var s_shadow: sampler_comparison;
+
+// Image based lighting
+@group(2) @binding(3)
+var t_irradiance: texture_cube<f32>;
+@group(2) @binding(4)
+var t_prefiltered: texture_cube<f32>;
+@group(2) @binding(5)
+var t_brdf_lut: texture_2d<f32>;
+@group(2) @binding(6)
+var s_environment: sampler;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    // Handedness of the bitangent in w
+    @location(3) tangent: vec4<f32>,
+}
+
+// Instance Input struct for drawing instances
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+    // Row of the material table, only read when drawing with one
+    @location(12) material: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) world_tangent: vec4<f32>,
+    @location(4) @interpolate(flat) material: u32,
+}
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.world_position = world_position.xyz;
+    out.world_normal = normal_matrix * model.normal;
+    // Tangents lie in the surface, so they transform with the model matrix itself
+    let tangent_matrix = mat3x3<f32>(model_matrix[0].xyz, model_matrix[1].xyz, model_matrix[2].xyz);
+    out.world_tangent = vec4<f32>(tangent_matrix * model.tangent.xyz, model.tangent.w);
+    out.clip_position = camera.view_proj * world_position;
+    out.material = instance.material;
+    return out;
+}
+
+// Fragment shader
+
+// glTF metallic-roughness material. The material bindings at group 0 and fs_main come from
+// one of the shader_material_*.wgsl files appended to this one.
+struct MaterialUniform {
+    base_color_factor: vec4<f32>,
+    emissive_factor: vec3<f32>,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+}
+
+// Samples of the five maps at the fragment
+struct MaterialMaps {
+    base_color: vec4<f32>,
+    metallic_roughness: vec4<f32>,
+    normal: vec4<f32>,
+    occlusion: vec4<f32>,
+    emissive: vec4<f32>,
+}
+
+const PI: f32 = 3.14159265359;
+
+// Returns 1.0 when lit and 0.0 when the point is hidden from the light
+fn point_shadow(light: PointLight, world_position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    if light.shadow_index < 0 {
+        return 1.0;
+    }
+
+    // Offset along the normal to avoid shadow acne on surfaces facing the light
+    let offset_position = world_position + normal * 0.02;
+    let light_to_fragment = offset_position - light.position;
+    let depth = length(light_to_fragment) / light.range;
+
+    return textureSampleCompareLevel(
+        t_shadow,
+        s_shadow,
+        light_to_fragment,
+        light.shadow_index,
+        depth - 0.005,
+    );
+}
+
+// Smooth falloff reaching zero at the light's range
+fn attenuation(distance: f32, range: f32) -> f32 {
+    let ratio = distance / range;
+    let window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    return window * window / (distance * distance + 1.0);
+}
+
+// Trowbridge-Reitz GGX normal distribution
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let denom = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * denom * denom);
+}
+
+// Smith height-correlated visibility term, includes the 1 / (4 n.l n.v) of Cook-Torrance
+fn visibility_smith_ggx(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let a2 = roughness * roughness * roughness * roughness;
+    let ggx_v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - a2) + a2);
+    let ggx_l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - a2) + a2);
+    return 0.5 / max(ggx_v + ggx_l, 1e-5);
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Fresnel for ambient light, where rough surfaces reflect less at grazing angles
+fn fresnel_schlick_roughness(cos_theta: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    return f0 + (max(vec3<f32>(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Perturbs the interpolated normal with the tangent space normal map
+fn surface_normal(in: VertexOutput, normal_map: vec4<f32>, normal_scale: f32) -> vec3<f32> {
+    let n = normalize(in.world_normal);
+    if light_data.normal_mapping == 0u {
+        return n;
+    }
+
+    // Re-orthogonalize, interpolation skews the tangent frame
+    let t = normalize(in.world_tangent.xyz - n * dot(n, in.world_tangent.xyz));
+    let b = cross(n, t) * in.world_tangent.w;
+
+    // Only x and y are read, z is rebuilt so BC5 normal maps work the same as RGBA ones
+    let xy = normal_map.xy * 2.0 - 1.0;
+    let z = sqrt(max(1.0 - dot(xy, xy), 0.0));
+    let tangent_normal = vec3<f32>(xy * normal_scale, z);
+    return normalize(mat3x3<f32>(t, b, n) * tangent_normal);
+}
+
+// Lights the fragment with the material's factors and its sampled maps
+fn shade(in: VertexOutput, material: MaterialUniform, maps: MaterialMaps) -> vec4<f32> {
+    let base_color = maps.base_color * material.base_color_factor;
+    let metallic = clamp(maps.metallic_roughness.b * material.metallic_factor, 0.0, 1.0);
+    // Fully smooth surfaces turn the specular highlight into a singularity
+    let roughness = clamp(maps.metallic_roughness.g * material.roughness_factor, 0.045, 1.0);
+    let occlusion = mix(1.0, maps.occlusion.r, material.occlusion_strength);
+    let emissive = maps.emissive.rgb * material.emissive_factor;
+
+    let normal = surface_normal(in, maps.normal, material.normal_scale);
+    let view_dir = normalize(camera.view_position.xyz - in.world_position);
+    let n_dot_v = max(dot(normal, view_dir), 1e-4);
+
+    // Dielectrics reflect 4% at normal incidence, metals reflect their base colour
+    let f0 = mix(vec3<f32>(0.04), base_color.rgb, metallic);
+    let diffuse_color = base_color.rgb * (1.0 - metallic);
+
+    // Ambient light from the environment, split sum approximation for the specular part
+    let reflect_dir = reflect(-view_dir, normal);
+    let max_lod = f32(textureNumLevels(t_prefiltered) - 1);
+    let prefiltered = textureSampleLevel(t_prefiltered, s_environment, reflect_dir, roughness * max_lod).rgb;
+    let brdf = textureSampleLevel(t_brdf_lut, s_environment, vec2<f32>(n_dot_v, roughness), 0.0).rg;
+    let irradiance = textureSampleLevel(t_irradiance, s_environment, normal, 0.0).rgb;
+    let ambient_fresnel = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+    let ambient_diffuse = (1.0 - ambient_fresnel) * irradiance * diffuse_color;
+    let ambient_specular = prefiltered * (f0 * brdf.x + brdf.y);
+
+    var color = (ambient_diffuse + ambient_specular) * occlusion * light_data.environment_intensity;
+
+    for (var i = 0u; i < light_data.count; i += 1u) {
+        let light = light_data.lights[i];
+        let to_light = light.position - in.world_position;
+        let distance = length(to_light);
+        let light_dir = to_light / distance;
+        let half_dir = normalize(view_dir + light_dir);
+
+        let n_dot_l = max(dot(normal, light_dir), 0.0);
+        let n_dot_h = max(dot(normal, half_dir), 0.0);
+        let v_dot_h = max(dot(view_dir, half_dir), 0.0);
+
+        let fresnel = fresnel_schlick(v_dot_h, f0);
+        let specular = fresnel * distribution_ggx(n_dot_h, roughness) * visibility_smith_ggx(n_dot_v, n_dot_l, roughness);
+        let diffuse = (1.0 - fresnel) * diffuse_color / PI;
+
+        let visibility = point_shadow(light, in.world_position, normal);
+        let radiance = light.color * attenuation(distance, light.range) * visibility;
+
+        color += (diffuse + specular) * radiance * n_dot_l;
+    }
+
+    color += emissive;
+
+    return vec4<f32>(color, base_color.a);
+}
@@ -0,0 +1,54 @@
+// Every material in a table, each map slot a texture array with one layer per texture
+
+// Must match MaterialEntry in material_table.rs
+struct MaterialEntry {
+    params: MaterialUniform,
+    // Layer of each map in its array, NO_MAP when the material has none
+    base_color: u32,
+    metallic_roughness: u32,
+    normal: u32,
+    occlusion: u32,
+    emissive: u32,
+}
+@group(0) @binding(0)
+var<storage, read> materials: array<MaterialEntry>;
+@group(0) @binding(1)
+var t_base_color: texture_2d_array<f32>;
+@group(0) @binding(2)
+var t_metallic_roughness: texture_2d_array<f32>;
+@group(0) @binding(3)
+var t_normal: texture_2d_array<f32>;
+@group(0) @binding(4)
+var t_occlusion: texture_2d_array<f32>;
+@group(0) @binding(5)
+var t_emissive: texture_2d_array<f32>;
+@group(0) @binding(6)
+var s_material: sampler;
+
+// Must match NO_MAP in material_table.rs
+const NO_MAP: u32 = 0xffffffffu;
+
+// Layer 0 for a map the material leaves out, its sample is replaced by the neutral value
+fn layer(index: u32) -> u32 {
+    return select(index, 0u, index == NO_MAP);
+}
+
+fn or_neutral(sampled: vec4<f32>, index: u32, neutral: vec4<f32>) -> vec4<f32> {
+    return select(sampled, neutral, index == NO_MAP);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let entry = materials[in.material];
+    var maps: MaterialMaps;
+    let uv = in.tex_coords;
+    let white = vec4<f32>(1.0);
+    // Textures are sampled here rather than passed to a function, so every backend can
+    // tell which sampler goes with which texture
+    maps.base_color = or_neutral(textureSample(t_base_color, s_material, uv, layer(entry.base_color)), entry.base_color, white);
+    maps.metallic_roughness = or_neutral(textureSample(t_metallic_roughness, s_material, uv, layer(entry.metallic_roughness)), entry.metallic_roughness, white);
+    maps.normal = or_neutral(textureSample(t_normal, s_material, uv, layer(entry.normal)), entry.normal, vec4<f32>(0.5, 0.5, 1.0, 1.0));
+    maps.occlusion = or_neutral(textureSample(t_occlusion, s_material, uv, layer(entry.occlusion)), entry.occlusion, white);
+    maps.emissive = or_neutral(textureSample(t_emissive, s_material, uv, layer(entry.emissive)), entry.emissive, white);
+    return shade(in, entry.params, maps);
+}
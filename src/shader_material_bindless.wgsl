@@ -0,0 +1,30 @@
+// Every material in a table, its maps indices into one binding array of all the textures
+
+// Must match MaterialEntry in material_table.rs
+struct MaterialEntry {
+    params: MaterialUniform,
+    // Index of each map in t_textures
+    base_color: u32,
+    metallic_roughness: u32,
+    normal: u32,
+    occlusion: u32,
+    emissive: u32,
+}
+@group(0) @binding(0)
+var<storage, read> materials: array<MaterialEntry>;
+@group(0) @binding(1)
+var t_textures: binding_array<texture_2d<f32>>;
+@group(0) @binding(2)
+var s_material: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let entry = materials[in.material];
+    var maps: MaterialMaps;
+    maps.base_color = textureSample(t_textures[entry.base_color], s_material, in.tex_coords);
+    maps.metallic_roughness = textureSample(t_textures[entry.metallic_roughness], s_material, in.tex_coords);
+    maps.normal = textureSample(t_textures[entry.normal], s_material, in.tex_coords);
+    maps.occlusion = textureSample(t_textures[entry.occlusion], s_material, in.tex_coords);
+    maps.emissive = textureSample(t_textures[entry.emissive], s_material, in.tex_coords);
+    return shade(in, entry.params, maps);
+}
@@ -0,0 +1,27 @@
+// One material per draw, bound with its own uniform and textures
+
+@group(0) @binding(0)
+var<uniform> material: MaterialUniform;
+@group(0) @binding(1)
+var t_base_color: texture_2d<f32>;
+@group(0) @binding(2)
+var t_metallic_roughness: texture_2d<f32>;
+@group(0) @binding(3)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(4)
+var t_occlusion: texture_2d<f32>;
+@group(0) @binding(5)
+var t_emissive: texture_2d<f32>;
+@group(0) @binding(6)
+var s_material: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var maps: MaterialMaps;
+    maps.base_color = textureSample(t_base_color, s_material, in.tex_coords);
+    maps.metallic_roughness = textureSample(t_metallic_roughness, s_material, in.tex_coords);
+    maps.normal = textureSample(t_normal, s_material, in.tex_coords);
+    maps.occlusion = textureSample(t_occlusion, s_material, in.tex_coords);
+    maps.emissive = textureSample(t_emissive, s_material, in.tex_coords);
+    return shade(in, material, maps);
+}
@@ -0,0 +1,28 @@
+// Flat coloured rectangles in normalized device coordinates, used for the frame time graph
+
+struct RectInput {
+    // Lower left and upper right corner
+    @location(0) bounds: vec4<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+// Four vertices drawn as a triangle strip per rectangle
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32, rect: RectInput) -> VertexOutput {
+    let corner = vec2<f32>(f32(in_vertex_index & 1u), f32(in_vertex_index >> 1u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(mix(rect.bounds.xy, rect.bounds.zw, corner), 0.0, 1.0);
+    out.color = rect.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
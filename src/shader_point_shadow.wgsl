@@ -0,0 +1,51 @@
+// Vertex shader
+
+// One cube face of a point light's shadow map
+struct ShadowFace {
+    view_proj: mat4x4<f32>,
+    light_position: vec3<f32>,
+    range: f32,
+};
+@group(0) @binding(0)
+var<uniform> face: ShadowFace;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+}
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+}
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
+    var out: VertexOutput;
+    out.world_position = world_position.xyz;
+    out.clip_position = face.view_proj * world_position;
+    return out;
+}
+
+// Fragment shader
+
+// Store the linear distance to the light so every face shares the same depth scale
+@fragment
+fn fs_main(in: VertexOutput) -> @builtin(frag_depth) f32 {
+    return length(in.world_position - face.light_position) / face.range;
+}
@@ -0,0 +1,40 @@
+// Vertex shader
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_sky_view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// One triangle covering the whole screen, placed on the far plane
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+
+    var out: VertexOutput;
+    out.ndc = vec2<f32>(x, y);
+    out.clip_position = vec4<f32>(x, y, 1.0, 1.0);
+    return out;
+}
+
+// Fragment shader
+
+@group(0) @binding(0)
+var t_skybox: texture_cube<f32>;
+@group(0) @binding(1)
+var s_skybox: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let world = camera.inv_sky_view_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    let dir = normalize(world.xyz / world.w);
+    return vec4<f32>(textureSampleLevel(t_skybox, s_skybox, dir, 0.0).rgb, 1.0);
+}
@@ -0,0 +1,276 @@
+use cgmath::{Matrix4, Point3, Vector3};
+
+use crate::camera::OPENGL_TO_WGPU_MATRIX;
+use crate::gpu_timer::GpuTimer;
+use crate::light::{self, PointLight, MAX_POINT_LIGHTS};
+use crate::texture;
+use crate::{InstanceRaw, Vertex};
+
+pub const SHADOW_MAP_SIZE: u32 = 1024;
+const FACES: usize = 6;
+
+// Cube face order is +X, -X, +Y, -Y, +Z, -Z, each as (forward, up)
+const CUBE_FACES: [([f32; 3], [f32; 3]); FACES] = [
+    ([1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+];
+
+// The face table above follows OpenGL's bottom-up texture rows, wgpu's rows run top-down
+#[rustfmt::skip]
+const FLIP_Y: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, -1.0, 0.0, 0.0,
+    0.0, 0.0, 1.0, 0.0,
+    0.0, 0.0, 0.0, 1.0,
+);
+
+// Data uploaded for every face of every shadow casting light
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowFaceUniform {
+    view_proj: [[f32; 4]; 4],
+    light_position: [f32; 3],
+    range: f32,
+}
+
+/// Omnidirectional shadow maps for point lights, stored as one cube map per light
+/// in a depth cube array. Each texel holds the distance to the light divided by its range.
+pub struct PointShadows {
+    #[allow(unused)]
+    texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+    face_views: Vec<wgpu::TextureView>,
+    face_buffer: wgpu::Buffer,
+    face_stride: u32,
+    face_bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl PointShadows {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("point_shadow_texture"),
+            size: wgpu::Extent3d {
+                width: SHADOW_MAP_SIZE,
+                height: SHADOW_MAP_SIZE,
+                depth_or_array_layers: (FACES * MAX_POINT_LIGHTS) as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: texture::Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("point_shadow_view"),
+            dimension: Some(wgpu::TextureViewDimension::CubeArray),
+            ..Default::default()
+        });
+
+        // One 2D view per face to render into
+        let face_views = (0..FACES * MAX_POINT_LIGHTS)
+            .map(|layer| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("point_shadow_face_view"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer as u32,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("point_shadow_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        // Face uniforms are read with a dynamic offset, so every entry has to be aligned
+        let alignment = device.limits().min_uniform_buffer_offset_alignment;
+        let face_size = size_of::<ShadowFaceUniform>() as u32;
+        let face_stride = face_size.div_ceil(alignment) * alignment;
+
+        let face_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("point_shadow_face_buffer"),
+            size: (face_stride as usize * FACES * MAX_POINT_LIGHTS) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let face_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("point_shadow_face_bind_group_layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(face_size as u64),
+                    },
+                    count: None,
+                }],
+            });
+
+        let face_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("point_shadow_face_bind_group"),
+            layout: &face_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &face_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(face_size as u64),
+                }),
+            }],
+        });
+
+        let pipeline = Self::create_pipeline(device, &face_bind_group_layout);
+
+        Self {
+            texture,
+            view,
+            sampler,
+            face_views,
+            face_buffer,
+            face_stride,
+            face_bind_group,
+            pipeline,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        face_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("point_shadow_shader_module"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shader_point_shadow.wgsl").into()),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("point_shadow_pipeline_layout"),
+            bind_group_layouts: &[face_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("point_shadow_pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader_module,
+                entry_point: Some("vs_main"),
+                buffers: &[Vertex::desc(), InstanceRaw::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            // The fragment stage only writes the linear distance to the light as depth
+            fragment: Some(wgpu::FragmentState {
+                module: &shader_module,
+                entry_point: Some("fs_main"),
+                targets: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // The y flip reverses the winding, so both sides are drawn
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Writes the view projection of every cube face for the shadow casting lights.
+    pub fn update(&self, queue: &wgpu::Queue, lights: &[PointLight]) {
+        for (light, index) in lights.iter().zip(light::shadow_indices(lights)) {
+            let Some(index) = index else {
+                continue;
+            };
+
+            let proj = OPENGL_TO_WGPU_MATRIX
+                * FLIP_Y
+                * cgmath::perspective(cgmath::Deg(90.0), 1.0, 0.05, light.range);
+
+            for (face, (forward, up)) in CUBE_FACES.iter().enumerate() {
+                let eye: Point3<f32> = light.position;
+                let view =
+                    Matrix4::look_at_rh(eye, eye + Vector3::from(*forward), Vector3::from(*up));
+                let uniform = ShadowFaceUniform {
+                    view_proj: (proj * view).into(),
+                    light_position: light.position.into(),
+                    range: light.range,
+                };
+                let offset = (index * FACES + face) as u32 * self.face_stride;
+                queue.write_buffer(
+                    &self.face_buffer,
+                    offset as wgpu::BufferAddress,
+                    bytemuck::cast_slice(&[uniform]),
+                );
+            }
+        }
+    }
+
+    /// Renders the shadow maps of all shadow casting lights, one depth pass per cube face.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        lights: &[PointLight],
+        mut timer: Option<&mut GpuTimer>,
+        draw: impl Fn(&mut wgpu::RenderPass),
+    ) {
+        for index in light::shadow_indices(lights).flatten() {
+            for face in 0..FACES {
+                let layer = index * FACES + face;
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("point_shadow_pass"),
+                    color_attachments: &[],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: &self.face_views[layer],
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(1.0),
+                            store: wgpu::StoreOp::Store,
+                        }),
+                        stencil_ops: None,
+                    }),
+                    occlusion_query_set: None,
+                    timestamp_writes: timer.as_deref_mut().and_then(|timer| timer.pass("shadows")),
+                });
+
+                render_pass.set_pipeline(&self.pipeline);
+                render_pass.set_bind_group(
+                    0,
+                    &self.face_bind_group,
+                    &[layer as u32 * self.face_stride],
+                );
+                draw(&mut render_pass);
+            }
+        }
+    }
+}
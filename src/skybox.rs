@@ -0,0 +1,186 @@
+use anyhow::{Context, Result};
+
+use crate::ibl::Environment;
+use crate::texture;
+use crate::vfs::Vfs;
+
+// Resolution of each face when projecting an equirectangular skybox
+const EQUIRECT_FACE_SIZE: u32 = 1024;
+
+/// Where the skybox cube map comes from.
+#[derive(Default)]
+pub enum SkyboxSource {
+    /// Show the environment map that lights the scene
+    #[default]
+    Environment,
+    /// Six images in wgpu's face order: +X, -X, +Y, -Y, +Z, -Z
+    Faces([String; 6]),
+    /// One equirectangular image, LDR or `.hdr`
+    Equirect(String),
+}
+
+/// Background drawn behind all geometry, at the far plane and rotating with the camera.
+pub struct Skybox {
+    #[allow(unused)]
+    texture: texture::Texture,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Skybox {
+    pub fn load(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        source: &SkyboxSource,
+        environment: &Environment,
+        vfs: &Vfs,
+    ) -> Result<texture::Texture> {
+        let open = |path: &String| -> Result<image::DynamicImage> {
+            let bytes = vfs.read(path)?;
+            image::load_from_memory(&bytes).with_context(|| format!("Failed to load {path}"))
+        };
+        match source {
+            SkyboxSource::Environment => Ok(texture::Texture {
+                texture: environment.cube.texture.clone(),
+                view: environment.cube.view.clone(),
+                sampler: environment.cube.sampler.clone(),
+            }),
+            SkyboxSource::Faces(paths) => {
+                let mut faces = Vec::with_capacity(6);
+                for path in paths {
+                    faces.push(open(path)?);
+                }
+                let faces: [image::DynamicImage; 6] =
+                    faces.try_into().unwrap_or_else(|_| unreachable!());
+                texture::Texture::cube_from_images(device, queue, &faces, "skybox_texture")
+            }
+            SkyboxSource::Equirect(path) => {
+                let img = open(path)?;
+                Ok(texture::Texture::cube_from_equirect(
+                    device,
+                    queue,
+                    &img,
+                    EQUIRECT_FACE_SIZE,
+                    "skybox_texture",
+                ))
+            }
+        }
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        texture: texture::Texture,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("skybox_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &texture);
+
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("skybox_shader_module"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shader_skybox.wgsl").into()),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("skybox_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout, camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("skybox_pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader_module,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader_module,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // Drawn at depth 1.0, so only pixels no geometry has touched pass the test
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            texture,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    /// Shows another cube map, such as one loaded with [`Self::load`] after the scene changed.
+    pub fn set_texture(&mut self, device: &wgpu::Device, texture: texture::Texture) {
+        self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &texture);
+        self.texture = texture;
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: &texture::Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("skybox_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+        })
+    }
+
+    /// Draws the skybox, expects the camera bind group at group 1 and the opaque geometry already drawn.
+    pub fn render(&self, render_pass: &mut wgpu::RenderPass) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
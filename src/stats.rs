@@ -0,0 +1,298 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+// Frames kept in the rolling history, a few seconds at common refresh rates
+pub const HISTORY_LEN: usize = 240;
+// Weight of the newest GPU timing in the running average
+const GPU_SMOOTHING: f64 = 0.1;
+
+/// CPU time spent on one frame.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct FrameTiming {
+    // From the start of this frame to the start of the next one
+    pub frame: Duration,
+    // Simulation and uploading the scene
+    pub update: Duration,
+    // Recording and submitting the command buffers
+    pub encode: Duration,
+}
+
+/// GPU time spent on the render passes sharing a name.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PassTiming {
+    pub name: &'static str,
+    pub duration: Duration,
+}
+
+/// Minimum, average, maximum and 99th percentile of one timing over the history.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Summary {
+    pub min: Duration,
+    pub avg: Duration,
+    pub max: Duration,
+    pub p99: Duration,
+}
+impl Summary {
+    fn new(mut samples: Vec<Duration>) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        samples.sort_unstable();
+        let total: Duration = samples.iter().sum();
+        // Nearest rank, so with fewer than 100 samples this is the maximum
+        let p99_rank = (samples.len() * 99).div_ceil(100);
+        Self {
+            min: samples[0],
+            avg: total / samples.len() as u32,
+            max: samples[samples.len() - 1],
+            p99: samples[p99_rank - 1],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StatsSummary {
+    pub frame: Summary,
+    pub update: Summary,
+    pub encode: Summary,
+    // Running average per pass, empty without timestamp queries
+    pub gpu: Vec<PassTiming>,
+}
+impl fmt::Display for StatsSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let fps = if self.frame.avg.is_zero() {
+            0.0
+        } else {
+            1.0 / self.frame.avg.as_secs_f64()
+        };
+        write!(
+            f,
+            "{fps:.0} fps | frame {:.2}/{:.2}/{:.2} ms (avg/p99/max) | update {:.2} ms | encode {:.2} ms",
+            ms(self.frame.avg),
+            ms(self.frame.p99),
+            ms(self.frame.max),
+            ms(self.update.avg),
+            ms(self.encode.avg),
+        )?;
+        for (index, pass) in self.gpu.iter().enumerate() {
+            let separator = if index == 0 { " | gpu" } else { "," };
+            write!(f, "{separator} {} {:.2} ms", pass.name, ms(pass.duration))?;
+        }
+        Ok(())
+    }
+}
+
+/// Rolling history of frame timings.
+pub struct FrameStats {
+    history: VecDeque<FrameTiming>,
+    // Timing of the frame in progress, its frame time is known once the next one starts
+    current: FrameTiming,
+    frame_start: Option<Instant>,
+    gpu: Vec<PassTiming>,
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        Self {
+            history: VecDeque::with_capacity(HISTORY_LEN),
+            current: FrameTiming::default(),
+            frame_start: None,
+            gpu: Vec::new(),
+        }
+    }
+}
+
+impl FrameStats {
+    /// Completes the previous frame and starts timing a new one.
+    pub fn begin_frame(&mut self, now: Instant) {
+        if let Some(frame_start) = self.frame_start {
+            self.current.frame = now.saturating_duration_since(frame_start);
+            self.push(self.current);
+        }
+        self.current = FrameTiming::default();
+        self.frame_start = Some(now);
+    }
+
+    pub fn record_update(&mut self, duration: Duration) {
+        self.current.update += duration;
+    }
+
+    pub fn record_encode(&mut self, duration: Duration) {
+        self.current.encode += duration;
+    }
+
+    /// Folds the GPU pass timings of a frame into the running averages. They arrive a few
+    /// frames late, so they are kept apart from the history.
+    pub fn record_gpu(&mut self, passes: &[PassTiming]) {
+        self.gpu = passes
+            .iter()
+            .map(
+                |pass| match self.gpu.iter().find(|old| old.name == pass.name) {
+                    Some(old) => PassTiming {
+                        name: pass.name,
+                        duration: old.duration.mul_f64(1.0 - GPU_SMOOTHING)
+                            + pass.duration.mul_f64(GPU_SMOOTHING),
+                    },
+                    None => *pass,
+                },
+            )
+            .collect();
+    }
+
+    pub fn push(&mut self, timing: FrameTiming) {
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(timing);
+    }
+
+    /// Completed frames, oldest first.
+    pub fn history(&self) -> impl ExactSizeIterator<Item = &FrameTiming> {
+        self.history.iter()
+    }
+
+    pub fn summary(&self) -> StatsSummary {
+        let samples =
+            |timing: fn(&FrameTiming) -> Duration| self.history.iter().map(timing).collect();
+        StatsSummary {
+            frame: Summary::new(samples(|timing| timing.frame)),
+            update: Summary::new(samples(|timing| timing.update)),
+            encode: Summary::new(samples(|timing| timing.encode)),
+            gpu: self.gpu.clone(),
+        }
+    }
+
+    /// Writes the history as CSV with one row per frame, times in milliseconds.
+    pub fn write_csv(&self, mut writer: impl Write) -> std::io::Result<()> {
+        writeln!(writer, "frame_ms,update_ms,encode_ms")?;
+        for timing in &self.history {
+            writeln!(
+                writer,
+                "{:.3},{:.3},{:.3}",
+                timing.frame.as_secs_f64() * 1000.0,
+                timing.update.as_secs_f64() * 1000.0,
+                timing.encode.as_secs_f64() * 1000.0,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn frame_time_is_known_when_the_next_frame_starts() {
+        let mut stats = FrameStats::default();
+        let start = Instant::now();
+        stats.begin_frame(start);
+        stats.record_update(ms(2));
+        stats.record_encode(ms(3));
+        assert_eq!(stats.history().len(), 0);
+
+        stats.begin_frame(start + ms(16));
+        assert_eq!(
+            stats.history().copied().collect::<Vec<_>>(),
+            vec![FrameTiming {
+                frame: ms(16),
+                update: ms(2),
+                encode: ms(3),
+            }]
+        );
+    }
+
+    #[test]
+    fn history_keeps_the_latest_frames() {
+        let mut stats = FrameStats::default();
+        for frame in 0..HISTORY_LEN as u64 + 10 {
+            stats.push(FrameTiming {
+                frame: ms(frame),
+                ..Default::default()
+            });
+        }
+        assert_eq!(stats.history().len(), HISTORY_LEN);
+        assert_eq!(stats.history().next().unwrap().frame, ms(10));
+    }
+
+    #[test]
+    fn summary_of_empty_history_is_zero() {
+        assert_eq!(FrameStats::default().summary(), StatsSummary::default());
+    }
+
+    #[test]
+    fn summary_finds_min_avg_max_and_p99() {
+        let mut stats = FrameStats::default();
+        // 1 to 200 ms, shuffled a little so sorting matters
+        for frame in (101..=200).chain(1..=100) {
+            stats.push(FrameTiming {
+                frame: ms(frame),
+                ..Default::default()
+            });
+        }
+        let summary = stats.summary().frame;
+        assert_eq!(summary.min, ms(1));
+        assert_eq!(summary.max, ms(200));
+        assert_eq!(summary.avg, Duration::from_micros(100_500));
+        assert_eq!(summary.p99, ms(198));
+    }
+
+    #[test]
+    fn gpu_timings_are_averaged_per_pass() {
+        let mut stats = FrameStats::default();
+        let pass = |name, millis| PassTiming {
+            name,
+            duration: ms(millis),
+        };
+        stats.record_gpu(&[pass("shadows", 10), pass("scene", 20)]);
+        stats.record_gpu(&[pass("scene", 30)]);
+        assert_eq!(stats.summary().gpu, vec![pass("scene", 21)]);
+    }
+
+    #[test]
+    fn summary_lists_gpu_passes() {
+        let summary = StatsSummary {
+            frame: Summary {
+                avg: ms(20),
+                ..Default::default()
+            },
+            gpu: vec![
+                PassTiming {
+                    name: "shadows",
+                    duration: Duration::from_micros(250),
+                },
+                PassTiming {
+                    name: "scene",
+                    duration: ms(1),
+                },
+            ],
+            ..Default::default()
+        };
+        assert!(summary
+            .to_string()
+            .ends_with("| gpu shadows 0.25 ms, scene 1.00 ms"));
+    }
+
+    #[test]
+    fn csv_has_a_row_per_frame() {
+        let mut stats = FrameStats::default();
+        stats.push(FrameTiming {
+            frame: ms(16),
+            update: Duration::from_micros(1500),
+            encode: ms(2),
+        });
+        let mut csv = Vec::new();
+        stats.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "frame_ms,update_ms,encode_ms\n16.000,1.500,2.000\n"
+        );
+    }
+}
@@ -0,0 +1,459 @@
+use anyhow::{bail, ensure, Context};
+use image::DynamicImage;
+use wgpu::TextureFormat;
+
+use crate::bc;
+
+const KTX2_MAGIC: &[u8; 12] = b"\xABKTX 20\xBB\r\n\x1A\n";
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+
+// Formats read from and written to DDS files with a DX10 header
+const DXGI_FORMATS: &[(ddsfile::DxgiFormat, TextureFormat)] = {
+    use ddsfile::DxgiFormat as D;
+    use TextureFormat as W;
+    &[
+        (D::R8_UNorm, W::R8Unorm),
+        (D::R8G8_UNorm, W::Rg8Unorm),
+        (D::R8G8B8A8_UNorm, W::Rgba8Unorm),
+        (D::R8G8B8A8_UNorm_sRGB, W::Rgba8UnormSrgb),
+        (D::R16G16B16A16_Float, W::Rgba16Float),
+        (D::R32G32B32A32_Float, W::Rgba32Float),
+        (D::BC1_UNorm, W::Bc1RgbaUnorm),
+        (D::BC1_UNorm_sRGB, W::Bc1RgbaUnormSrgb),
+        (D::BC2_UNorm, W::Bc2RgbaUnorm),
+        (D::BC2_UNorm_sRGB, W::Bc2RgbaUnormSrgb),
+        (D::BC3_UNorm, W::Bc3RgbaUnorm),
+        (D::BC3_UNorm_sRGB, W::Bc3RgbaUnormSrgb),
+        (D::BC4_UNorm, W::Bc4RUnorm),
+        (D::BC4_SNorm, W::Bc4RSnorm),
+        (D::BC5_UNorm, W::Bc5RgUnorm),
+        (D::BC5_SNorm, W::Bc5RgSnorm),
+        (D::BC6H_UF16, W::Bc6hRgbUfloat),
+        (D::BC6H_SF16, W::Bc6hRgbFloat),
+        (D::BC7_UNorm, W::Bc7RgbaUnorm),
+        (D::BC7_UNorm_sRGB, W::Bc7RgbaUnormSrgb),
+    ]
+};
+
+/// What a texture holds, which decides its colour space and how it is compressed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TextureKind {
+    Color,
+    // Linear values in any channel, such as metallic and roughness
+    Data,
+    // Only x and y are kept when compressed, the shader rebuilds z
+    Normal,
+    // Only the red channel is read, such as occlusion
+    Mask,
+}
+
+impl TextureKind {
+    pub fn is_srgb(self) -> bool {
+        self == Self::Color
+    }
+}
+
+/// Texels of a texture as read from a file, in the format they are uploaded in.
+pub struct TextureData {
+    pub width: u32,
+    pub height: u32,
+    pub format: TextureFormat,
+    // Mip levels from the full size down, rows of texels or blocks without padding
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl TextureData {
+    /// Reads a KTX2 or DDS container, or any image the image crate decodes. `srgb` picks the
+    /// colour space of files that do not say which one they are in.
+    pub fn decode(bytes: &[u8], srgb: bool) -> anyhow::Result<Self> {
+        if bytes.starts_with(KTX2_MAGIC) {
+            from_ktx2(bytes)
+        } else if bytes.starts_with(DDS_MAGIC) {
+            from_dds(bytes, srgb)
+        } else {
+            let image = image::load_from_memory(bytes).context("could not decode it")?;
+            Ok(Self::from_image(&image, srgb))
+        }
+    }
+
+    pub fn from_image(image: &DynamicImage, srgb: bool) -> Self {
+        let (width, height) = (image.width(), image.height());
+        match image {
+            // HDR and EXR, kept in floats so values above one survive
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => Self {
+                width,
+                height,
+                format: TextureFormat::Rgba32Float,
+                levels: vec![bytemuck::cast_slice(image.to_rgba32f().as_raw()).to_vec()],
+            },
+            _ => Self {
+                width,
+                height,
+                format: if srgb {
+                    TextureFormat::Rgba8UnormSrgb
+                } else {
+                    TextureFormat::Rgba8Unorm
+                },
+                levels: vec![image.to_rgba8().into_raw()],
+            },
+        }
+    }
+
+    /// Bytes the upload writes to the GPU.
+    pub fn size(&self) -> usize {
+        self.levels.iter().map(Vec::len).sum()
+    }
+
+    /// Block compresses an 8 bit image in the format that suits `kind`. Other formats, and
+    /// images whose sides are not multiples of 4, are returned as they are.
+    pub fn compressed(self, kind: TextureKind) -> Self {
+        let compressible = matches!(
+            self.format,
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb
+        ) && self.levels.len() == 1
+            && self.width.is_multiple_of(4)
+            && self.height.is_multiple_of(4);
+        if !compressible {
+            return self;
+        }
+        let (rgba, width, height) = (&self.levels[0], self.width, self.height);
+        let (format, encoded) = match kind {
+            TextureKind::Normal => (
+                TextureFormat::Bc5RgUnorm,
+                bc::encode_bc5(rgba, width, height),
+            ),
+            TextureKind::Mask => (
+                TextureFormat::Bc4RUnorm,
+                bc::encode_bc4(rgba, width, height),
+            ),
+            TextureKind::Color | TextureKind::Data if self.format.is_srgb() => (
+                TextureFormat::Bc7RgbaUnormSrgb,
+                bc::encode_bc7(rgba, width, height),
+            ),
+            TextureKind::Color | TextureKind::Data => (
+                TextureFormat::Bc7RgbaUnorm,
+                bc::encode_bc7(rgba, width, height),
+            ),
+        };
+        let levels = vec![encoded];
+        Self {
+            format,
+            levels,
+            ..self
+        }
+    }
+
+    /// The texture as a DDS file, which [`Self::decode`] reads back unchanged.
+    pub fn to_dds(&self) -> anyhow::Result<Vec<u8>> {
+        let format = DXGI_FORMATS
+            .iter()
+            .find(|(_, format)| *format == self.format)
+            .map(|(dxgi, _)| *dxgi)
+            .with_context(|| format!("{:?} cannot be written to DDS", self.format))?;
+        let mut dds = ddsfile::Dds::new_dxgi(ddsfile::NewDxgiParams {
+            height: self.height,
+            width: self.width,
+            depth: None,
+            format,
+            mipmap_levels: Some(self.levels.len() as u32),
+            array_layers: None,
+            caps2: None,
+            is_cubemap: false,
+            resource_dimension: ddsfile::D3D10ResourceDimension::Texture2D,
+            alpha_mode: ddsfile::AlphaMode::Unknown,
+        })?;
+        dds.data = self.levels.concat();
+        let mut bytes = Vec::new();
+        dds.write(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Converts the texels to a format that can be filtered with `features`, or fails when
+    /// the GPU cannot sample them at all.
+    pub fn supported(self, features: wgpu::Features) -> anyhow::Result<Self> {
+        if self.format == TextureFormat::Rgba32Float
+            && !features.contains(wgpu::Features::FLOAT32_FILTERABLE)
+        {
+            let levels = self
+                .levels
+                .iter()
+                .map(|level| {
+                    level
+                        .chunks_exact(4)
+                        .map(|c| half::f16::from_f32(f32::from_le_bytes(c.try_into().unwrap())))
+                        .flat_map(half::f16::to_le_bytes)
+                        .collect()
+                })
+                .collect();
+            return Ok(Self {
+                format: TextureFormat::Rgba16Float,
+                levels,
+                ..self
+            });
+        }
+        let missing = self.format.required_features() - features;
+        ensure!(
+            missing.is_empty(),
+            "{:?} textures need {missing:?}, which this GPU does not support",
+            self.format
+        );
+        Ok(self)
+    }
+
+    // Containers are checked up front, wgpu would only fail validation on upload
+    fn checked(self) -> anyhow::Result<Self> {
+        let (block_width, block_height) = self.format.block_dimensions();
+        ensure!(
+            self.width.is_multiple_of(block_width) && self.height.is_multiple_of(block_height),
+            "{}x{} is not a multiple of the {block_width}x{block_height} blocks of {:?}",
+            self.width,
+            self.height,
+            self.format
+        );
+        let size = wgpu::Extent3d {
+            width: self.width,
+            height: self.height,
+            depth_or_array_layers: 1,
+        };
+        ensure!(
+            !self.levels.is_empty()
+                && self.levels.len() as u32 <= size.max_mips(wgpu::TextureDimension::D2),
+            "{} mip levels do not fit a {}x{} texture",
+            self.levels.len(),
+            self.width,
+            self.height
+        );
+        for (level, bytes) in self.levels.iter().enumerate() {
+            let expected = level_size(self.format, self.width, self.height, level as u32);
+            ensure!(
+                bytes.len() == expected,
+                "mip level {level} has {} bytes instead of {expected}",
+                bytes.len()
+            );
+        }
+        Ok(self)
+    }
+}
+
+/// Bytes of mip `level` of a `width` x `height` texture in `format`.
+pub fn level_size(format: TextureFormat, width: u32, height: u32, level: u32) -> usize {
+    let (block_width, block_height) = format.block_dimensions();
+    let blocks_wide = (width >> level).max(1).div_ceil(block_width);
+    let blocks_high = (height >> level).max(1).div_ceil(block_height);
+    let block_size = format.block_copy_size(None).unwrap_or(4);
+    (blocks_wide * blocks_high * block_size) as usize
+}
+
+fn from_ktx2(bytes: &[u8]) -> anyhow::Result<TextureData> {
+    let reader = ktx2::Reader::new(bytes).context("not a valid KTX2 file")?;
+    let header = reader.header();
+    if let Some(scheme) = header.supercompression_scheme {
+        bail!("supercompressed ({scheme:?}) KTX2 files are not supported");
+    }
+    ensure!(
+        header.pixel_height > 0
+            && header.pixel_depth <= 1
+            && header.layer_count <= 1
+            && header.face_count == 1,
+        "only single 2D KTX2 textures are supported, not arrays, cubes or volumes"
+    );
+    let format = header
+        .format
+        .and_then(ktx2_format)
+        .with_context(|| format!("KTX2 format {:?} is not supported", header.format))?;
+    TextureData {
+        width: header.pixel_width,
+        height: header.pixel_height,
+        format,
+        levels: reader.levels().map(|level| level.data.to_vec()).collect(),
+    }
+    .checked()
+}
+
+fn ktx2_format(format: ktx2::Format) -> Option<TextureFormat> {
+    use ktx2::Format as K;
+    use TextureFormat as W;
+    Some(match format {
+        K::R8_UNORM => W::R8Unorm,
+        K::R8G8_UNORM => W::Rg8Unorm,
+        K::R8G8B8A8_UNORM => W::Rgba8Unorm,
+        K::R8G8B8A8_SRGB => W::Rgba8UnormSrgb,
+        K::R16G16B16A16_SFLOAT => W::Rgba16Float,
+        K::R32G32B32A32_SFLOAT => W::Rgba32Float,
+        K::BC1_RGB_UNORM_BLOCK | K::BC1_RGBA_UNORM_BLOCK => W::Bc1RgbaUnorm,
+        K::BC1_RGB_SRGB_BLOCK | K::BC1_RGBA_SRGB_BLOCK => W::Bc1RgbaUnormSrgb,
+        K::BC2_UNORM_BLOCK => W::Bc2RgbaUnorm,
+        K::BC2_SRGB_BLOCK => W::Bc2RgbaUnormSrgb,
+        K::BC3_UNORM_BLOCK => W::Bc3RgbaUnorm,
+        K::BC3_SRGB_BLOCK => W::Bc3RgbaUnormSrgb,
+        K::BC4_UNORM_BLOCK => W::Bc4RUnorm,
+        K::BC4_SNORM_BLOCK => W::Bc4RSnorm,
+        K::BC5_UNORM_BLOCK => W::Bc5RgUnorm,
+        K::BC5_SNORM_BLOCK => W::Bc5RgSnorm,
+        K::BC6H_UFLOAT_BLOCK => W::Bc6hRgbUfloat,
+        K::BC6H_SFLOAT_BLOCK => W::Bc6hRgbFloat,
+        K::BC7_UNORM_BLOCK => W::Bc7RgbaUnorm,
+        K::BC7_SRGB_BLOCK => W::Bc7RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+fn from_dds(bytes: &[u8], srgb: bool) -> anyhow::Result<TextureData> {
+    let dds = ddsfile::Dds::read(bytes).context("not a valid DDS file")?;
+    ensure!(
+        dds.get_depth() <= 1 && dds.get_num_array_layers() == 1,
+        "only single 2D DDS textures are supported, not arrays, cubes or volumes"
+    );
+    let format = match (dds.get_dxgi_format(), dds.get_d3d_format()) {
+        (Some(format), _) => DXGI_FORMATS
+            .iter()
+            .find(|(dxgi, _)| *dxgi == format)
+            .map(|(_, format)| *format)
+            .with_context(|| format!("DDS format {format:?} is not supported"))?,
+        (None, Some(format)) => d3d_format(format, srgb)
+            .with_context(|| format!("DDS format {format:?} is not supported"))?,
+        (None, None) => bail!("DDS file does not say its format"),
+    };
+    let (width, height) = (dds.get_width(), dds.get_height());
+
+    // The levels of the only layer follow one another at the start of the data
+    let mut levels = Vec::new();
+    let mut offset = 0;
+    for level in 0..dds.get_num_mipmap_levels().max(1) {
+        let size = level_size(format, width, height, level);
+        let bytes = dds
+            .data
+            .get(offset..offset + size)
+            .with_context(|| format!("DDS data ends before mip level {level}"))?;
+        levels.push(bytes.to_vec());
+        offset += size;
+    }
+    TextureData {
+        width,
+        height,
+        format,
+        levels,
+    }
+    .checked()
+}
+
+// Legacy headers do not record a colour space, the material slot decides
+fn d3d_format(format: ddsfile::D3DFormat, srgb: bool) -> Option<TextureFormat> {
+    use ddsfile::D3DFormat as D;
+    use TextureFormat as W;
+    let format = match format {
+        D::A8B8G8R8 => W::Rgba8Unorm,
+        D::DXT1 => W::Bc1RgbaUnorm,
+        D::DXT3 => W::Bc2RgbaUnorm,
+        D::DXT5 => W::Bc3RgbaUnorm,
+        D::A16B16G16R16F => return Some(W::Rgba16Float),
+        D::A32B32G32R32F => return Some(W::Rgba32Float),
+        _ => return None,
+    };
+    Some(if srgb {
+        format.add_srgb_suffix()
+    } else {
+        format
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dds(format: ddsfile::DxgiFormat, width: u32, height: u32, mips: u32) -> Vec<u8> {
+        let mut dds = ddsfile::Dds::new_dxgi(ddsfile::NewDxgiParams {
+            height,
+            width,
+            depth: None,
+            format,
+            mipmap_levels: Some(mips),
+            array_layers: None,
+            caps2: None,
+            is_cubemap: false,
+            resource_dimension: ddsfile::D3D10ResourceDimension::Texture2D,
+            alpha_mode: ddsfile::AlphaMode::Unknown,
+        })
+        .unwrap();
+        for (i, byte) in dds.data.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let mut bytes = Vec::new();
+        dds.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn block_compressed_levels_round_up_to_whole_blocks() {
+        assert_eq!(
+            level_size(TextureFormat::Bc7RgbaUnorm, 16, 8, 0),
+            4 * 2 * 16
+        );
+        assert_eq!(level_size(TextureFormat::Bc7RgbaUnorm, 16, 8, 2), 16);
+        assert_eq!(level_size(TextureFormat::Bc1RgbaUnorm, 16, 8, 4), 8);
+        assert_eq!(level_size(TextureFormat::Rgba16Float, 16, 8, 1), 8 * 4 * 8);
+    }
+
+    #[test]
+    fn dds_keeps_its_format_and_mips() {
+        let bytes = dds(ddsfile::DxgiFormat::BC7_UNorm_sRGB, 16, 8, 5);
+        let data = TextureData::decode(&bytes, false).unwrap();
+        assert_eq!(data.format, TextureFormat::Bc7RgbaUnormSrgb);
+        assert_eq!((data.width, data.height), (16, 8));
+        let sizes = data.levels.iter().map(Vec::len).collect::<Vec<_>>();
+        assert_eq!(sizes, [128, 32, 16, 16, 16]);
+        assert_eq!(data.levels[1][0], 128);
+
+        let odd = dds(ddsfile::DxgiFormat::BC1_UNorm, 10, 8, 1);
+        assert!(TextureData::decode(&odd, true).is_err());
+    }
+
+    #[test]
+    fn float_images_fall_back_to_half_floats() {
+        let image = image::Rgb32FImage::from_pixel(2, 1, image::Rgb([4.0, 0.5, 0.0]));
+        let data = TextureData::from_image(&DynamicImage::ImageRgb32F(image), true);
+        assert_eq!(data.format, TextureFormat::Rgba32Float);
+        assert_eq!(data.size(), 2 * 16);
+
+        let halves = data.supported(wgpu::Features::empty()).unwrap();
+        assert_eq!(halves.format, TextureFormat::Rgba16Float);
+        let expected = [4.0, 0.5, 0.0, 1.0].map(half::f16::from_f32);
+        assert_eq!(
+            halves.levels[0][..8],
+            *bytemuck::cast_slice::<_, u8>(&expected)
+        );
+
+        let bc = || TextureData {
+            width: 4,
+            height: 4,
+            format: TextureFormat::Bc5RgUnorm,
+            levels: vec![vec![0; 16]],
+        };
+        assert!(bc().supported(wgpu::Features::empty()).is_err());
+        assert!(bc()
+            .supported(wgpu::Features::TEXTURE_COMPRESSION_BC)
+            .is_ok());
+    }
+
+    #[test]
+    fn images_compress_by_kind_and_read_back_from_dds() {
+        let pixel = image::Rgba([200, 100, 50, 255]);
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(8, 4, pixel));
+        let normal = TextureData::from_image(&image, false).compressed(TextureKind::Normal);
+        assert_eq!(normal.format, TextureFormat::Bc5RgUnorm);
+        assert_eq!(normal.size(), 2 * 16);
+        let mask = TextureData::from_image(&image, false).compressed(TextureKind::Mask);
+        assert_eq!(mask.format, TextureFormat::Bc4RUnorm);
+
+        let color = TextureData::from_image(&image, true).compressed(TextureKind::Color);
+        assert_eq!(color.format, TextureFormat::Bc7RgbaUnormSrgb);
+        let read = TextureData::decode(&color.to_dds().unwrap(), false).unwrap();
+        assert_eq!(read.format, color.format);
+        assert_eq!(read.levels, color.levels);
+
+        // Sides that are not whole blocks stay uncompressed
+        let odd = DynamicImage::ImageRgba8(image::RgbaImage::new(6, 4));
+        let odd = TextureData::from_image(&odd, false).compressed(TextureKind::Data);
+        assert_eq!(odd.format, TextureFormat::Rgba8Unorm);
+    }
+}
@@ -0,0 +1,316 @@
+use std::borrow::Cow;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::pack::Pack;
+
+/// Directory of loose assets used when nothing else is configured.
+pub const DEFAULT_ROOT: &str = "assets";
+/// Pack mounted when it exists and no other is given.
+pub const DEFAULT_PACK: &str = "assets.pak";
+
+// Assets compiled into the binary, so the demo runs without any files next to it
+const BUILT_IN: &[(&str, &[u8])] = &[
+    ("happy-tree.png", include_bytes!("../assets/happy-tree.png")),
+    (
+        "brick-normal.png",
+        include_bytes!("../assets/brick-normal.png"),
+    ),
+    (
+        "environment.hdr",
+        include_bytes!("../assets/environment.hdr"),
+    ),
+];
+
+/// Turns `path` into the form assets are looked up by: forward slashes, no `.` parts and
+/// `..` folded into the part before it. Absolute paths keep their leading slash.
+pub fn normalize(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let absolute = path.starts_with('/');
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => (),
+            ".." if parts.last().is_some_and(|last| *last != "..") => {
+                parts.pop();
+            }
+            // Nothing is above the root
+            ".." if absolute => (),
+            part => parts.push(part),
+        }
+    }
+    let joined = parts.join("/");
+    if absolute {
+        format!("/{joined}")
+    } else {
+        joined
+    }
+}
+
+/// Resolves `path` as written in a file in `dir`.
+pub fn join(dir: &str, path: &str) -> String {
+    if is_absolute(path) || dir.is_empty() {
+        normalize(path)
+    } else {
+        normalize(&format!("{dir}/{path}"))
+    }
+}
+
+/// `path` written relative to `dir`, the inverse of [`join`]. Paths that cannot be
+/// reached from `dir` are returned as they are.
+pub fn relative(dir: &str, path: &str) -> String {
+    let (dir, path) = (normalize(dir), normalize(path));
+    if is_absolute(&dir) != is_absolute(&path) {
+        return path;
+    }
+    let dir_parts = dir
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>();
+    let path_parts = path
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>();
+    let common = dir_parts
+        .iter()
+        .zip(&path_parts)
+        .take_while(|(a, b)| a == b)
+        .count();
+    if dir_parts[common..].contains(&"..") {
+        return path;
+    }
+    let mut parts = vec![".."; dir_parts.len() - common];
+    parts.extend(&path_parts[common..]);
+    parts.join("/")
+}
+
+fn is_absolute(path: &str) -> bool {
+    path.starts_with('/') || path.starts_with('\\') || Path::new(path).is_absolute()
+}
+
+fn not_found(path: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{path} not found"))
+}
+
+/// Somewhere assets can be read from, by their normalized relative path.
+pub trait Source: Send + Sync {
+    fn read(&self, path: &str) -> io::Result<Cow<'static, [u8]>>;
+    fn contains(&self, path: &str) -> bool;
+}
+
+/// Loose files under a directory, edited without rebuilding.
+pub struct Directory {
+    root: PathBuf,
+}
+impl Directory {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+impl Source for Directory {
+    fn read(&self, path: &str) -> io::Result<Cow<'static, [u8]>> {
+        std::fs::read(self.root.join(path)).map(Cow::Owned)
+    }
+
+    fn contains(&self, path: &str) -> bool {
+        self.root.join(path).is_file()
+    }
+}
+
+/// Files compiled into the binary.
+pub struct Embedded {
+    files: &'static [(&'static str, &'static [u8])],
+}
+impl Embedded {
+    pub fn built_in() -> Self {
+        Self { files: BUILT_IN }
+    }
+}
+impl Source for Embedded {
+    fn read(&self, path: &str) -> io::Result<Cow<'static, [u8]>> {
+        self.files
+            .iter()
+            .find(|(name, _)| *name == path)
+            .map(|(_, bytes)| Cow::Borrowed(*bytes))
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn contains(&self, path: &str) -> bool {
+        self.files.iter().any(|(name, _)| *name == path)
+    }
+}
+
+/// Every place assets are read from, searched in the order they were mounted. Relative
+/// paths are looked up in the sources, absolute ones are read from disk as they are.
+#[derive(Default)]
+pub struct Vfs {
+    sources: Vec<Box<dyn Source>>,
+    // Directory of the first loose source, files inside it get relative paths
+    root: Option<PathBuf>,
+    // Where imported assets, such as compressed textures, are kept between runs
+    cache: Option<PathBuf>,
+}
+
+impl Vfs {
+    /// Loose files in `root` when it exists, then `packs`, then the built-in assets.
+    pub fn standard(root: &Path, packs: Vec<Pack>) -> Self {
+        let mut vfs = Self::default();
+        if root.is_dir() {
+            vfs.mount_directory(root);
+        }
+        for pack in packs {
+            vfs.mount(pack);
+        }
+        vfs.mount(Embedded::built_in());
+        vfs
+    }
+
+    pub fn set_cache(&mut self, dir: impl Into<PathBuf>) {
+        self.cache = Some(dir.into());
+    }
+
+    pub fn cache(&self) -> Option<&Path> {
+        self.cache.as_deref()
+    }
+
+    pub fn mount(&mut self, source: impl Source + 'static) {
+        self.sources.push(Box::new(source));
+    }
+
+    pub fn mount_directory(&mut self, root: &Path) {
+        if self.root.is_none() {
+            self.root = Some(std::path::absolute(root).unwrap_or_else(|_| root.to_path_buf()));
+        }
+        self.mount(Directory::new(root));
+    }
+
+    pub fn read(&self, path: &str) -> io::Result<Cow<'static, [u8]>> {
+        let path = normalize(path);
+        if is_absolute(&path) {
+            return std::fs::read(&path).map(Cow::Owned);
+        }
+        for source in &self.sources {
+            match source.read(&path) {
+                Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                result => return result,
+            }
+        }
+        Err(not_found(&path))
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        let path = normalize(path);
+        if is_absolute(&path) {
+            return Path::new(&path).is_file();
+        }
+        self.sources.iter().any(|source| source.contains(&path))
+    }
+
+    /// Finds `path` as written in a file in `dir`: next to the file first, then from the
+    /// asset root, so files outside the root can still refer to the assets in it.
+    pub fn resolve(&self, dir: &str, path: &str) -> Option<String> {
+        [join(dir, path), normalize(path)]
+            .into_iter()
+            .find(|candidate| self.exists(candidate))
+    }
+
+    /// Path by which a file or directory on disk is found, relative to the asset root when
+    /// it is inside it.
+    pub fn locate(&self, path: &Path) -> String {
+        let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        match self
+            .root
+            .as_ref()
+            .and_then(|root| absolute.strip_prefix(root).ok())
+        {
+            Some(relative) => normalize(&relative.to_string_lossy()),
+            None => normalize(&absolute.to_string_lossy()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_normalized() {
+        assert_eq!(normalize("textures/./brick.png"), "textures/brick.png");
+        assert_eq!(normalize("textures\\brick.png"), "textures/brick.png");
+        assert_eq!(
+            normalize("scenes/../textures//brick.png"),
+            "textures/brick.png"
+        );
+        assert_eq!(normalize("../brick.png"), "../brick.png");
+        assert_eq!(normalize("/tmp/../brick.png"), "/brick.png");
+        assert_eq!(normalize("/../brick.png"), "/brick.png");
+    }
+
+    #[test]
+    fn paths_resolve_relative_to_their_file() {
+        assert_eq!(
+            join("scenes", "../textures/brick.png"),
+            "textures/brick.png"
+        );
+        assert_eq!(join("", "brick.png"), "brick.png");
+        assert_eq!(join("scenes", "/tmp/brick.png"), "/tmp/brick.png");
+
+        assert_eq!(
+            relative("scenes", "textures/brick.png"),
+            "../textures/brick.png"
+        );
+        assert_eq!(relative("scenes", "scenes/brick.png"), "brick.png");
+        assert_eq!(relative("", "brick.png"), "brick.png");
+        assert_eq!(relative("/tmp/scenes", "/tmp/brick.png"), "../brick.png");
+        assert_eq!(relative("scenes", "/tmp/brick.png"), "/tmp/brick.png");
+        for (dir, path) in [("a/b", "a/c/d.png"), ("", "x/y.png"), ("a", "a.png")] {
+            assert_eq!(join(dir, &relative(dir, path)), path);
+        }
+    }
+
+    #[test]
+    fn sources_are_searched_in_order() {
+        struct One(&'static str, &'static [u8]);
+        impl Source for One {
+            fn read(&self, path: &str) -> io::Result<Cow<'static, [u8]>> {
+                if path == self.0 {
+                    Ok(Cow::Borrowed(self.1))
+                } else {
+                    Err(not_found(path))
+                }
+            }
+            fn contains(&self, path: &str) -> bool {
+                path == self.0
+            }
+        }
+
+        let mut vfs = Vfs::default();
+        vfs.mount(One("brick.png", b"loose"));
+        vfs.mount(One("brick.png", b"packed"));
+        vfs.mount(One("tree.png", b"packed"));
+
+        assert_eq!(&*vfs.read("./brick.png").unwrap(), b"loose");
+        assert_eq!(&*vfs.read("textures/../tree.png").unwrap(), b"packed");
+        assert!(vfs.exists("tree.png"));
+        assert_eq!(
+            vfs.resolve("scenes", "../tree.png").as_deref(),
+            Some("tree.png")
+        );
+        assert_eq!(
+            vfs.resolve("/tmp/scenes", "tree.png").as_deref(),
+            Some("tree.png")
+        );
+        assert_eq!(vfs.resolve("scenes", "missing.png"), None);
+        assert!(!vfs.exists("missing.png"));
+        let error = vfs.read("missing.png").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert_eq!(error.to_string(), "missing.png not found");
+    }
+
+    #[test]
+    fn built_in_assets_are_embedded() {
+        let vfs = Vfs::standard(Path::new("/nonexistent"), Vec::new());
+        assert!(vfs.exists("happy-tree.png"));
+        assert!(!vfs.read("environment.hdr").unwrap().is_empty());
+    }
+}